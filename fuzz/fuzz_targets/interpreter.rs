@@ -0,0 +1,56 @@
+//! Generated programs through the interpreter, with some declarations
+//! dropped so that undefined names come up. The generator's bounds on
+//! loops and nesting keep every run short; each must finish or stop with a
+//! `RuntimeError`. Step and time limits stop a run the generator failed to
+//! bound instead of hanging the fuzzer.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pl0::ast::{Block, Decl};
+use pl0::generate::{generate, GeneratorOptions};
+use pl0::interpreter::{Interpreter, Limits, MemoryIo, Overflow, RunMode};
+use pl0::lexer::IntWidth;
+use std::time::Duration;
+
+fn drop_decls(block: &mut Block, choices: &mut impl Iterator<Item = u8>) {
+    block.decls.retain(|_| choices.next().is_none_or(|c| c % 8 != 0));
+    for decl in &mut block.decls {
+        if let Decl::Procedure { ref mut block, .. } = *decl {
+            drop_decls(block, choices);
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 12 {
+        return;
+    }
+    let seed = u64::from_le_bytes(data[..8].try_into().unwrap());
+    let options = GeneratorOptions {
+        max_nesting: data[8] as usize % 3,
+        max_items: 1 + data[9] as usize % 5,
+        max_depth: data[10] as usize % 4,
+        max_iterations: 1 + (data[10] / 4) as i64 % 5,
+    };
+    let mode = RunMode {
+        width: if data[11] & 1 == 0 { IntWidth::I32 } else { IntWidth::I64 },
+        overflow: match data[11] / 2 % 3 {
+            0 => Overflow::Checked,
+            1 => Overflow::Wrapping,
+            _ => Overflow::Saturating,
+        },
+    };
+
+    let mut program = generate(seed, options);
+    drop_decls(&mut program.block, &mut data[12..].iter().copied());
+
+    let mut interpreter = Interpreter::new(program);
+    interpreter.set_mode(mode);
+    interpreter.set_limits(Limits {
+        max_steps: Some(1_000_000),
+        timeout: Some(Duration::from_secs(1)),
+        ..Limits::default()
+    });
+    let _ = interpreter.run_with(&mut MemoryIo::new(""));
+});
@@ -0,0 +1,31 @@
+//! Arbitrary bytes through both lexers. Every input must end in tokens or a
+//! `LexError`, and the two lexers must give the same tokens or the same
+//! error.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pl0::lexer::{r_lexer_with, IntWidth, LexerOptions, ReadLexer};
+
+fuzz_target!(|data: &[u8]| {
+    let (flags, source) = match data.split_first() {
+        Some((flags, source)) => (*flags, source),
+        None => return,
+    };
+    let options = LexerOptions {
+        case_insensitive_keywords: flags & 1 != 0,
+        case_insensitive_idents: flags & 2 != 0,
+        keep_comments: flags & 4 != 0,
+        max_ident_len: if flags & 8 != 0 { Some(8) } else { None },
+        int_width: if flags & 16 != 0 { IntWidth::I64 } else { IntWidth::I32 },
+    };
+
+    // Invalid UTF-8 is an I/O error for the streaming lexer.
+    let streamed: Result<Vec<_>, _> = ReadLexer::new(source, options).collect();
+    if let Ok(source) = std::str::from_utf8(source) {
+        let whole = r_lexer_with(source, options).map(|tokens| {
+            tokens.into_iter().map(|t| t.into_owned()).collect::<Vec<_>>()
+        });
+        assert_eq!(whole, streamed);
+    }
+});
@@ -0,0 +1,69 @@
+//! Arbitrary token sequences through the parser, one byte per token. Every
+//! sequence must give a program or a list of syntax errors, and a program
+//! must read back the same once formatted.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use pl0::format::{format_program, FormatOptions};
+use pl0::lexer::{r_lexer, Pos, Radix, Token, TokenKind};
+use pl0::parser::parse_program;
+
+const TOKENS: &[(TokenKind<&str>, &str)] = &[
+    (TokenKind::Keyword("BEGIN"), "BEGIN"),
+    (TokenKind::Keyword("END"), "END"),
+    (TokenKind::Keyword("PROCEDURE"), "PROCEDURE"),
+    (TokenKind::Keyword("WHILE"), "WHILE"),
+    (TokenKind::Keyword("DO"), "DO"),
+    (TokenKind::Keyword("IF"), "IF"),
+    (TokenKind::Keyword("THEN"), "THEN"),
+    (TokenKind::Keyword("CALL"), "CALL"),
+    (TokenKind::Keyword("ODD"), "ODD"),
+    (TokenKind::Keyword("VAR"), "VAR"),
+    (TokenKind::Keyword("CONST"), "CONST"),
+    (TokenKind::Keyword("AND"), "AND"),
+    (TokenKind::Keyword("OR"), "OR"),
+    (TokenKind::Keyword("NOT"), "NOT"),
+    (TokenKind::Keyword("MOD"), "MOD"),
+    (TokenKind::Separator(":="), ":="),
+    (TokenKind::Separator("<="), "<="),
+    (TokenKind::Separator(">="), ">="),
+    (TokenKind::Separator("<"), "<"),
+    (TokenKind::Separator(">"), ">"),
+    (TokenKind::Separator("="), "="),
+    (TokenKind::Separator("#"), "#"),
+    (TokenKind::Separator(","), ","),
+    (TokenKind::Separator("."), "."),
+    (TokenKind::Separator(";"), ";"),
+    (TokenKind::Separator("+"), "+"),
+    (TokenKind::Separator("-"), "-"),
+    (TokenKind::Separator("*"), "*"),
+    (TokenKind::Separator("/"), "/"),
+    (TokenKind::Separator("%"), "%"),
+    (TokenKind::Separator("!"), "!"),
+    (TokenKind::Separator("?"), "?"),
+    (TokenKind::Separator("("), "("),
+    (TokenKind::Separator(")"), ")"),
+    (TokenKind::Ident("x"), "x"),
+    (TokenKind::Ident("y"), "y"),
+    (TokenKind::Ident("p"), "p"),
+    (TokenKind::Number(0, Radix::Decimal), "0"),
+    (TokenKind::Number(7, Radix::Hex), "0x7"),
+    (TokenKind::Str("s\\n"), "\"s\\n\""),
+];
+
+fuzz_target!(|data: &[u8]| {
+    let tokens: Vec<Token> = data.iter().enumerate().map(|(i, byte)| {
+        let (kind, text) = TOKENS[*byte as usize % TOKENS.len()];
+        Token { kind, pos: Pos { line: 1, col: i + 1 }, text }
+    }).collect();
+
+    match parse_program(&tokens) {
+        Ok(program) => {
+            let source = format_program(&program, &[], FormatOptions::default());
+            let reparsed = parse_program(&r_lexer(&source).unwrap()).unwrap();
+            assert_eq!(format_program(&reparsed, &[], FormatOptions::default()), source);
+        }
+        Err(failure) => assert!(!failure.errors.is_empty()),
+    }
+});
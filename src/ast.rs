@@ -0,0 +1,175 @@
+use lexer::{Pos, Radix};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use symbol::Symbol;
+
+/// An identifier together with where it was written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ident {
+    pub name: Symbol,
+    pub span: Span,
+}
+
+/// `Div` truncates towards zero and `Mod` takes the sign of the dividend,
+/// so `a = (a / b) * b + a MOD b` holds for negative operands too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExOp {
+    Equal,
+    NumberSign,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogicOp {
+    And,
+    Or
+}
+
+/// The first and last positions of a token or node; `end` is the position
+/// of its last character. Grouping parentheses are not part of the span of
+/// the expression or condition inside them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl Span {
+    /// From the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { start: self.start, end: other.end }
+    }
+}
+
+// Operators carry the position of their token for runtime errors.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Expr {
+    Number(Span, i64, Radix),
+    Ident(Ident),
+    Negate(Pos, Box<Expr>),
+    Binary {lhs: Box<Expr>, op: BinOp, pos: Pos, rhs: Box<Expr>},
+    // A function provided by the host, e.g. `now()`. `span` ends at the `)`.
+    Call {span: Span, name: Ident, args: Vec<Expr>},
+}
+
+impl Expr {
+    pub fn span(&self) -> Span {
+        match *self {
+            Expr::Number(span, ..) | Expr::Call {span, ..} => span,
+            Expr::Ident(ref ident) => ident.span,
+            Expr::Negate(pos, ref e) => Span { start: pos, end: e.span().end },
+            Expr::Binary {ref lhs, ref rhs, ..} => lhs.span().to(rhs.span()),
+        }
+    }
+}
+
+// `ODD` and `NOT` carry the position of their keyword.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Cond {
+    Odd(Pos, Expr),
+    Compare {lhs: Expr, op: ExOp, rhs: Expr},
+    Not(Pos, Box<Cond>),
+    Logic {lhs: Box<Cond>, op: LogicOp, rhs: Box<Cond>},
+}
+
+impl Cond {
+    pub fn span(&self) -> Span {
+        match *self {
+            Cond::Odd(pos, ref e) => Span { start: pos, end: e.span().end },
+            Cond::Compare {ref lhs, ref rhs, ..} => lhs.span().to(rhs.span()),
+            Cond::Not(pos, ref c) => Span { start: pos, end: c.span().end },
+            Cond::Logic {ref lhs, ref rhs, ..} => lhs.span().to(rhs.span()),
+        }
+    }
+}
+
+/// One item of a `!` statement. Strings hold their unescaped contents and
+/// the span of the literal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WriteItem {
+    Str(Span, String),
+    Expr(Expr),
+}
+
+/// What the parser expected and what it found instead. Statements and
+/// declarations it had to skip are kept in the tree as `Error` nodes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SyntaxError {
+    /// E.g. "`;` or `END`" or "expression".
+    pub expected: String,
+    /// The span and text of the offending token, `None` at the end of input.
+    pub found: Option<(Span, String)>,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.found {
+            Some((span, ref text)) => write!(f, "{}: expected {}, found `{}`", span.start, self.expected, text),
+            None => write!(f, "expected {}, found end of input", self.expected),
+        }
+    }
+}
+
+/// Every statement but `Empty` and `Error` carries its span, from its first
+/// token to its last.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Stmt {
+    Assign {span: Span, target: Ident, value: Expr},
+    Call(Span, Ident),
+    Read(Span, Ident),
+    Write {span: Span, items: Vec<WriteItem>, newline: bool},
+    BeginEnd(Span, Vec<Stmt>),
+    IfThen {span: Span, condition: Cond, statement: Box<Stmt>},
+    WhileDo {span: Span, condition: Cond, statement: Box<Stmt>},
+    Empty,
+    Error(SyntaxError),
+}
+
+impl Stmt {
+    /// `None` for an empty statement, and for an error at the end of input.
+    pub fn span(&self) -> Option<Span> {
+        match *self {
+            Stmt::Assign {span, ..} | Stmt::Call(span, _) | Stmt::Read(span, _) | Stmt::Write {span, ..} |
+            Stmt::BeginEnd(span, _) | Stmt::IfThen {span, ..} | Stmt::WhileDo {span, ..} => Some(span),
+            Stmt::Empty => None,
+            Stmt::Error(ref e) => e.found.as_ref().map(|found| found.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Decl {
+    /// `span` covers `name = value`.
+    Const {span: Span, name: Ident, value: i64, radix: Radix},
+    Var(Ident),
+    /// `span` runs from `PROCEDURE` to the end of the body.
+    Procedure {span: Span, name: Ident, block: Box<Block>},
+    Error(SyntaxError),
+}
+
+/// Declarations are kept in source order: constants, then variables, then
+/// procedures.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Block {
+    /// From the block's first token to its last, `None` if it has none.
+    pub span: Option<Span>,
+    pub decls: Vec<Decl>,
+    pub statement: Stmt,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+    pub block: Block,
+}
@@ -0,0 +1,192 @@
+//! Semantic analysis: every name a statement or expression uses must be
+//! declared and be the right kind of thing.
+//!
+//! Names resolve lexically, as in the symbol table, with native procedures
+//! and functions as if declared around the main program.
+
+use ast::*;
+use interpreter::Natives;
+use std::fmt;
+use symbol::{SymbolKind, SymbolTable};
+use visit::{walk_decl, walk_expr, walk_stmt, Visitor};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SemanticErrorKind {
+    Undeclared,
+    // Assigned to or read into.
+    NotAVariable,
+    // Used as an operand.
+    NotAValue,
+    NotAProcedure,
+    NotAFunction,
+    WrongArgumentCount {expected: usize, found: usize},
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemanticError {
+    pub ident: Ident,
+    pub kind: SemanticErrorKind,
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (pos, name) = (self.ident.span.start, &self.ident.name);
+        match self.kind {
+            SemanticErrorKind::Undeclared => write!(f, "{}: `{}` is not declared", pos, name),
+            SemanticErrorKind::NotAVariable => write!(f, "{}: `{}` is not a variable", pos, name),
+            SemanticErrorKind::NotAValue => write!(f, "{}: `{}` is not a constant or variable", pos, name),
+            SemanticErrorKind::NotAProcedure => write!(f, "{}: `{}` is not a procedure", pos, name),
+            SemanticErrorKind::NotAFunction => write!(f, "{}: `{}` is not a function", pos, name),
+            SemanticErrorKind::WrongArgumentCount {expected, found} => {
+                let plural = if expected == 1 { "" } else { "s" };
+                write!(f, "{}: `{}` takes {} argument{} but got {}", pos, name, expected, plural, found)
+            }
+        }
+    }
+}
+
+/// Every misused name in `program`, in source order. `symbols` must have
+/// been built from `program`.
+pub fn check(program: &Program, symbols: &SymbolTable, natives: &Natives) -> Vec<SemanticError> {
+    let mut checker = Checker {
+        symbols,
+        natives,
+        scope: 0,
+        scopes_seen: 0,
+        errors: Vec::new()
+    };
+    checker.visit_program(program);
+    checker.errors
+}
+
+struct Checker<'a> {
+    symbols: &'a SymbolTable,
+    natives: &'a Natives,
+    // The symbol table scope of the block being checked.
+    scope: usize,
+    // Procedures are numbered in the order the symbol table adds them.
+    scopes_seen: usize,
+    errors: Vec<SemanticError>,
+}
+
+impl<'a> Checker<'a> {
+    fn error(&mut self, ident: &Ident, kind: SemanticErrorKind) {
+        self.errors.push(SemanticError { ident: ident.clone(), kind });
+    }
+    
+    fn declared(&self, ident: &Ident) -> Option<SymbolKind> {
+        self.symbols.lookup(self.scope, &ident.name).map(|d| d.kind)
+    }
+    
+    fn is_native(&self, ident: &Ident) -> bool {
+        self.natives.has_procedure(&ident.name) || self.natives.function_arity(&ident.name).is_some()
+    }
+    
+    fn variable(&mut self, ident: &Ident) {
+        match self.declared(ident) {
+            Some(SymbolKind::Var) => {}
+            Some(_) => self.error(ident, SemanticErrorKind::NotAVariable),
+            None if self.is_native(ident) => self.error(ident, SemanticErrorKind::NotAVariable),
+            None => self.error(ident, SemanticErrorKind::Undeclared),
+        }
+    }
+    
+    fn value(&mut self, ident: &Ident) {
+        match self.declared(ident) {
+            Some(SymbolKind::Var) | Some(SymbolKind::Const(_)) => {}
+            Some(SymbolKind::Procedure) => self.error(ident, SemanticErrorKind::NotAValue),
+            None if self.is_native(ident) => self.error(ident, SemanticErrorKind::NotAValue),
+            None => self.error(ident, SemanticErrorKind::Undeclared),
+        }
+    }
+    
+    fn procedure(&mut self, ident: &Ident) {
+        match self.declared(ident) {
+            Some(SymbolKind::Procedure) => {}
+            Some(_) => self.error(ident, SemanticErrorKind::NotAProcedure),
+            None if self.natives.has_procedure(&ident.name) => {}
+            None if self.is_native(ident) => self.error(ident, SemanticErrorKind::NotAProcedure),
+            None => self.error(ident, SemanticErrorKind::Undeclared),
+        }
+    }
+    
+    fn function(&mut self, ident: &Ident, found: usize) {
+        if self.declared(ident).is_some() {
+            return self.error(ident, SemanticErrorKind::NotAFunction);
+        }
+        match self.natives.function_arity(&ident.name) {
+            Some(expected) if expected != found => {
+                self.error(ident, SemanticErrorKind::WrongArgumentCount { expected, found })
+            }
+            Some(_) => {}
+            None if self.is_native(ident) => self.error(ident, SemanticErrorKind::NotAFunction),
+            None => self.error(ident, SemanticErrorKind::Undeclared),
+        }
+    }
+}
+
+impl<'a, 'ast> Visitor<'ast> for Checker<'a> {
+    fn visit_decl(&mut self, decl: &'ast Decl) {
+        if let Decl::Procedure {..} = *decl {
+            let outer = self.scope;
+            self.scopes_seen += 1;
+            self.scope = self.scopes_seen;
+            walk_decl(self, decl);
+            self.scope = outer;
+        }
+    }
+    
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        match *stmt {
+            Stmt::Assign {ref target, ..} | Stmt::Read(_, ref target) => self.variable(target),
+            Stmt::Call(_, ref ident) => self.procedure(ident),
+            _ => {}
+        }
+        walk_stmt(self, stmt)
+    }
+    
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        match *expr {
+            Expr::Ident(ref ident) => self.value(ident),
+            Expr::Call {ref name, ref args, ..} => self.function(name, args.len()),
+            _ => {}
+        }
+        walk_expr(self, expr)
+    }
+}
+
+#[test]
+fn test_check() {
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    let program = parse_program(&r_lexer("
+CONST k = 1;
+VAR x;
+PROCEDURE p;
+  VAR beep;
+  BEGIN beep := rand(6); CALL beep END;
+BEGIN
+  x := now() + k; CALL beep; CALL p;
+  k := y; ? p; x := p + x(1);
+  CALL x; CALL now; x := beep + rand(1, 2)
+END.").unwrap()).unwrap();
+    let mut natives = Natives::new();
+    natives.procedure("beep", |_| Ok(()))
+        .function("now", 0, |_| Ok(0))
+        .function("rand", 1, |args| Ok(args[0]));
+
+    let errors: Vec<String> = check(&program, &SymbolTable::new(&program), &natives).iter().map(|e| e.to_string()).collect();
+    assert_eq!(errors, [
+        "6:31: `beep` is not a procedure",
+        "9:3: `k` is not a variable",
+        "9:8: `y` is not declared",
+        "9:13: `p` is not a variable",
+        "9:21: `p` is not a constant or variable",
+        "9:25: `x` is not a function",
+        "10:8: `x` is not a procedure",
+        "10:16: `now` is not a procedure",
+        "10:26: `beep` is not a constant or variable",
+        "10:33: `rand` takes 1 argument but got 2",
+    ]);
+}
@@ -0,0 +1,151 @@
+//! Differential testing: runs a program through every execution engine with
+//! the same input and reports the first engine that disagrees with the
+//! reference.
+//!
+//! The interpreter is the reference and, for now, the only engine. A
+//! bytecode VM or a native backend joins the comparison by implementing
+//! `Engine` and being listed in `engines`.
+
+use ast::Program;
+use format::{format_program, FormatOptions};
+use interpreter::{Interpreter, Limits, MemoryIo, RunMode, RuntimeError};
+use std::fmt;
+
+/// Everything a run printed and how it ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub output: String,
+    pub result: Result<(), RuntimeError>,
+}
+
+pub trait Engine {
+    fn name(&self) -> &'static str;
+    fn run(&self, program: &Program, input: &str, mode: RunMode, limits: Limits) -> Outcome;
+}
+
+pub struct InterpreterEngine;
+
+impl Engine for InterpreterEngine {
+    fn name(&self) -> &'static str {
+        "interpreter"
+    }
+    
+    fn run(&self, program: &Program, input: &str, mode: RunMode, limits: Limits) -> Outcome {
+        let mut interpreter = Interpreter::new(program.clone());
+        interpreter.set_mode(mode);
+        interpreter.set_limits(limits);
+        
+        let mut io = MemoryIo::new(input);
+        let result = interpreter.run_with(&mut io);
+        Outcome { output: io.output, result }
+    }
+}
+
+/// Every engine, the reference first.
+pub fn engines() -> Vec<Box<dyn Engine>> {
+    vec![Box::new(InterpreterEngine)]
+}
+
+/// A program and input on which an engine disagrees with the reference.
+#[derive(Debug)]
+pub struct Divergence {
+    pub program: Program,
+    pub input: String,
+    pub reference: (&'static str, Outcome),
+    pub other: (&'static str, Outcome),
+}
+
+fn describe(result: &Result<(), RuntimeError>) -> String {
+    match *result {
+        Ok(()) => "finished".to_string(),
+        Err(ref e) => format!("runtime error: {}", e),
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (reference, ref expected) = self.reference;
+        let (other, ref actual) = self.other;
+        writeln!(f, "{} diverges from {}", other, reference)?;
+        
+        let mut expected_lines = expected.output.lines();
+        let mut actual_lines = actual.output.lines();
+        for line in 1.. {
+            match (expected_lines.next(), actual_lines.next()) {
+                (None, None) => break,
+                (e, a) if e == a => continue,
+                (e, a) => {
+                    writeln!(f, "first different output line {}:", line)?;
+                    writeln!(f, "  {}: {:?}", reference, e.unwrap_or("<end of output>"))?;
+                    writeln!(f, "  {}: {:?}", other, a.unwrap_or("<end of output>"))?;
+                    break;
+                }
+            }
+        }
+        if expected.output.lines().eq(actual.output.lines()) && expected.output != actual.output {
+            writeln!(f, "output differs in line endings")?;
+        }
+        if expected.result != actual.result {
+            writeln!(f, "  {} {}", reference, describe(&expected.result))?;
+            writeln!(f, "  {} {}", other, describe(&actual.result))?;
+        }
+        
+        writeln!(f, "program:")?;
+        write!(f, "{}", format_program(&self.program, &[], FormatOptions::default()))?;
+        writeln!(f, "input:")?;
+        write!(f, "{}", self.input)
+    }
+}
+
+/// Runs `program` on `input` through each of `engines` and returns the
+/// reference outcome, or the first engine whose outcome differs from it.
+/// Every engine runs under the same `limits`.
+pub fn compare(program: &Program, input: &str, mode: RunMode, limits: Limits, engines: &[Box<dyn Engine>]) -> Result<Outcome, Box<Divergence>> {
+    let reference = &engines[0];
+    let expected = reference.run(program, input, mode, limits);
+    
+    for engine in &engines[1..] {
+        let actual = engine.run(program, input, mode, limits);
+        if actual != expected {
+            return Err(Box::new(Divergence {
+                program: program.clone(),
+                input: input.to_string(),
+                reference: (reference.name(), expected),
+                other: (engine.name(), actual)
+            }));
+        }
+    }
+    Ok(expected)
+}
+
+#[test]
+fn test_compare_reports_divergence() {
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    // Stands in for a backend that gets `/` wrong for negative operands.
+    struct FlooringEngine;
+
+    impl Engine for FlooringEngine {
+        fn name(&self) -> &'static str {
+            "flooring"
+        }
+
+        fn run(&self, program: &Program, input: &str, mode: RunMode, limits: Limits) -> Outcome {
+            let mut outcome = InterpreterEngine.run(program, input, mode, limits);
+            outcome.output = outcome.output.replace("-3", "-4");
+            outcome
+        }
+    }
+
+    let program = parse_program(&r_lexer("VAR x; BEGIN ? x; ! x / 2; ! -x / 2 END.").unwrap()).unwrap();
+    let engines: Vec<Box<dyn Engine>> = vec![Box::new(InterpreterEngine), Box::new(FlooringEngine)];
+
+    assert_eq!(compare(&program, "4\n", RunMode::default(), Limits::default(), &engines).unwrap().output, "2\n-2\n");
+
+    let divergence = compare(&program, "7\n", RunMode::default(), Limits::default(), &engines).unwrap_err();
+    let report = divergence.to_string();
+    assert!(report.starts_with("flooring diverges from interpreter\nfirst different output line 2:\n"));
+    assert!(report.contains("  interpreter: \"-3\"\n  flooring: \"-4\"\n"));
+    assert!(report.ends_with("! -(x / 2)\nEND.\ninput:\n7\n"));
+}
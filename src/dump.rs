@@ -0,0 +1,264 @@
+//! Machine-readable dumps of the AST.
+//!
+//! JSON follows serde's default layout: structs are objects, unit variants
+//! are strings and other variants are single-key objects named after the
+//! variant, e.g. `{"Var": {"name": "x", "span": {"start": {"line": 1, "col":
+//! 5}, "end": {"line": 1, "col": 5}}}}`.
+//! It keeps every field, so `from_json(&to_json(p))` gives back `p`.
+//!
+//! S-expressions are for reading and diffing; they leave out positions and
+//! literal radixes.
+
+use ast::*;
+use lexer::{escape_string, IntWidth, Pos};
+use serde_json;
+use std::fmt;
+use visit::{walk_decl, walk_expr, Visitor};
+
+pub fn to_json(program: &Program) -> String {
+    serde_json::to_string_pretty(program).expect("the AST always serialises")
+}
+
+/// Reads an AST back. Only its shape is checked; `check_literals` finds
+/// the numbers the lexer would not have accepted.
+pub fn from_json(json: &str) -> Result<Program, serde_json::Error> {
+    serde_json::from_str(json)
+}
+
+/// A number literal that is negative or does not fit in `width` bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiteralError {
+    pub pos: Pos,
+    pub value: i64,
+    pub width: IntWidth,
+}
+
+impl fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.value < 0 {
+            write!(f, "{}: number literal {} is negative", self.pos, self.value)
+        } else {
+            write!(f, "{}: number literal {} does not fit in {} bits", self.pos, self.value, self.width.bits())
+        }
+    }
+}
+
+/// The literals of `program` that the lexer would have rejected for
+/// `width`, in source order. A constant's is reported at its name.
+pub fn check_literals(program: &Program, width: IntWidth) -> Vec<LiteralError> {
+    let mut checker = LiteralChecker { width, errors: Vec::new() };
+    checker.visit_program(program);
+    checker.errors
+}
+
+struct LiteralChecker {
+    width: IntWidth,
+    errors: Vec<LiteralError>,
+}
+
+impl LiteralChecker {
+    fn check(&mut self, pos: Pos, value: i64) {
+        if value < 0 || value > self.width.max() {
+            self.errors.push(LiteralError { pos, value, width: self.width });
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for LiteralChecker {
+    fn visit_decl(&mut self, decl: &'ast Decl) {
+        if let Decl::Const {span, value, ..} = *decl {
+            self.check(span.start, value);
+        }
+        walk_decl(self, decl)
+    }
+    
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        if let Expr::Number(span, value, _) = *expr {
+            self.check(span.start, value);
+        }
+        walk_expr(self, expr)
+    }
+}
+
+/// `(program (block (var x) (assign x (+ x 1))))` and so on.
+pub fn to_sexp(program: &Program) -> String {
+    let mut out = String::new();
+    out.push_str("(program ");
+    block(&mut out, &program.block);
+    out.push(')');
+    out
+}
+
+fn block(out: &mut String, block: &Block) {
+    out.push_str("(block");
+    for decl in &block.decls {
+        out.push(' ');
+        match *decl {
+            Decl::Const {ref name, value, ..} => out.push_str(&format!("(const {} {})", name.name, value)),
+            Decl::Var(ref name) => out.push_str(&format!("(var {})", name.name)),
+            Decl::Procedure {ref name, ref block, ..} => {
+                out.push_str(&format!("(procedure {} ", name.name));
+                self::block(out, block);
+                out.push(')');
+            }
+            Decl::Error(ref e) => out.push_str(&format!("(error \"{}\")", escape_string(&e.to_string()))),
+        }
+    }
+    out.push(' ');
+    stmt(out, &block.statement);
+    out.push(')');
+}
+
+fn stmt(out: &mut String, stmt: &Stmt) {
+    match *stmt {
+        Stmt::Assign {ref target, ref value, ..} => {
+            out.push_str(&format!("(assign {} ", target.name));
+            expr(out, value);
+            out.push(')');
+        }
+        Stmt::Call(_, ref ident) => out.push_str(&format!("(call {})", ident.name)),
+        Stmt::Read(_, ref ident) => out.push_str(&format!("(read {})", ident.name)),
+        Stmt::Write {ref items, newline, ..} => {
+            out.push_str(if newline { "(writeln" } else { "(write" });
+            for item in items {
+                out.push(' ');
+                match *item {
+                    WriteItem::Str(_, ref s) => out.push_str(&format!("\"{}\"", escape_string(s))),
+                    WriteItem::Expr(ref e) => expr(out, e),
+                }
+            }
+            out.push(')');
+        }
+        Stmt::BeginEnd(_, ref statements) => {
+            out.push_str("(begin");
+            for s in statements {
+                out.push(' ');
+                self::stmt(out, s);
+            }
+            out.push(')');
+        }
+        Stmt::IfThen {ref condition, ref statement, ..} => {
+            out.push_str("(if ");
+            cond(out, condition);
+            out.push(' ');
+            self::stmt(out, statement);
+            out.push(')');
+        }
+        Stmt::WhileDo {ref condition, ref statement, ..} => {
+            out.push_str("(while ");
+            cond(out, condition);
+            out.push(' ');
+            self::stmt(out, statement);
+            out.push(')');
+        }
+        Stmt::Empty => out.push_str("(empty)"),
+        Stmt::Error(ref e) => out.push_str(&format!("(error \"{}\")", escape_string(&e.to_string()))),
+    }
+}
+
+fn cond(out: &mut String, cond: &Cond) {
+    match *cond {
+        Cond::Odd(_, ref e) => {
+            out.push_str("(odd ");
+            expr(out, e);
+            out.push(')');
+        }
+        Cond::Compare {ref lhs, op, ref rhs} => {
+            let op = match op {
+                ExOp::Equal => "=",
+                ExOp::NumberSign => "#",
+                ExOp::LessThan => "<",
+                ExOp::LessThanOrEqual => "<=",
+                ExOp::GreaterThan => ">",
+                ExOp::GreaterThanOrEqual => ">=",
+            };
+            out.push_str(&format!("({} ", op));
+            expr(out, lhs);
+            out.push(' ');
+            expr(out, rhs);
+            out.push(')');
+        }
+        Cond::Not(_, ref c) => {
+            out.push_str("(not ");
+            self::cond(out, c);
+            out.push(')');
+        }
+        Cond::Logic {ref lhs, op, ref rhs} => {
+            out.push_str(match op {
+                LogicOp::And => "(and ",
+                LogicOp::Or => "(or ",
+            });
+            self::cond(out, lhs);
+            out.push(' ');
+            self::cond(out, rhs);
+            out.push(')');
+        }
+    }
+}
+
+fn expr(out: &mut String, expr: &Expr) {
+    match *expr {
+        Expr::Number(_, n, _) => out.push_str(&n.to_string()),
+        Expr::Ident(ref ident) => out.push_str(&ident.name),
+        Expr::Negate(_, ref e) => {
+            out.push_str("(neg ");
+            self::expr(out, e);
+            out.push(')');
+        }
+        Expr::Binary {ref lhs, op, ref rhs, ..} => {
+            let op = match op {
+                BinOp::Add => "+",
+                BinOp::Sub => "-",
+                BinOp::Mul => "*",
+                BinOp::Div => "/",
+                BinOp::Mod => "mod",
+            };
+            out.push_str(&format!("({} ", op));
+            self::expr(out, lhs);
+            out.push(' ');
+            self::expr(out, rhs);
+            out.push(')');
+        }
+        Expr::Call {ref name, ref args, ..} => {
+            out.push_str(&format!("(funcall {}", name.name));
+            for arg in args {
+                out.push(' ');
+                self::expr(out, arg);
+            }
+            out.push(')');
+        }
+    }
+}
+
+#[test]
+fn test_sexp() {
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    let ast = parse_program(&r_lexer("CONST k = 0x10; VAR x; PROCEDURE p; x := -x MOD k; \
+                                      BEGIN ? x; IF NOT ODD x OR x # 1 THEN CALL p; WHILE x < 3 DO x := x + 1; ! \"x=\\\"\", x, END.").unwrap()).unwrap();
+
+    assert_eq!(to_sexp(&ast), "(program (block (const k 16) (var x) \
+                               (procedure p (block (assign x (neg (mod x k))))) \
+                               (begin (read x) (if (or (not (odd x)) (# x 1)) (call p)) \
+                               (while (< x 3) (assign x (+ x 1))) (write \"x=\\\"\" x))))");
+}
+
+#[test]
+fn test_json_round_trip() {
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    let ast = parse_program(&r_lexer("CONST k = 0b11; VAR x; BEGIN x := k * 2; IF x >= 6 AND 1 = 1 THEN ! \"ok\" END.").unwrap()).unwrap();
+    let json = to_json(&ast);
+
+    assert!(json.contains("\"Binary\""));
+    assert!(json.contains("\"radix\": \"Binary\""));
+    assert_eq!(from_json(&json).unwrap(), ast);
+
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["block"]["decls"][1]["Var"]["name"], "x");
+    assert_eq!(value["block"]["decls"][1]["Var"]["span"]["start"]["col"], 21);
+
+    assert!(from_json("{\"block\": {}}").is_err());
+}
@@ -0,0 +1,390 @@
+//! Canonical PL/0 source from an AST.
+//!
+//! Keywords are uppercase, operators are surrounded by single spaces and
+//! parentheses are only kept where precedence needs them. Comments are not
+//! part of the AST; they are passed in as tokens and printed on their own
+//! line before the first declaration or statement that follows them.
+
+use ast::*;
+use lexer::{escape_string, format_number, Pos, Token};
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FormatOptions {
+    /// Spaces per nesting level.
+    pub indent: usize,
+    /// Put every constant and variable on its own line.
+    pub one_decl_per_line: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: 2,
+            one_decl_per_line: false
+        }
+    }
+}
+
+/// Formats `program`. `comments` are the `Comment` tokens of its source, in
+/// source order.
+pub fn format_program(program: &Program, comments: &[Token], options: FormatOptions) -> String {
+    let mut f = Formatter {
+        out: String::new(),
+        comments: comments.iter().map(|c| (c.pos, c.text)).collect(),
+        options
+    };
+    
+    f.block(&program.block, ".");
+    f.flush_comments(None, 0);
+    f.out
+}
+
+struct Formatter<'a> {
+    out: String,
+    comments: VecDeque<(Pos, &'a str)>,
+    options: FormatOptions,
+}
+
+impl<'a> Formatter<'a> {
+    fn indent(&mut self, level: usize) {
+        for _ in 0..level * self.options.indent {
+            self.out.push(' ');
+        }
+    }
+    
+    // Prints the comments that come before `pos`, or all of them.
+    fn flush_comments(&mut self, pos: Option<Pos>, level: usize) {
+        while let Some(&(at, text)) = self.comments.front() {
+            if pos.is_some_and(|pos| at > pos) {
+                break;
+            }
+            self.comments.pop_front();
+            self.indent(level);
+            self.out.push_str(text);
+            self.out.push('\n');
+        }
+    }
+    
+    fn line_start(&mut self, pos: Option<Pos>, level: usize) {
+        self.flush_comments(pos, level);
+        self.indent(level);
+    }
+    
+    // Declarations and statements of a block always start at column one;
+    // only the statements nest.
+    fn block(&mut self, block: &Block, terminator: &str) {
+        let consts: Vec<(&Ident, i64, _)> = block.decls.iter().filter_map(|d| match *d {
+            Decl::Const {ref name, value, radix, ..} => Some((name, value, radix)),
+            _ => None
+        }).collect();
+        let vars: Vec<&Ident> = block.decls.iter().filter_map(|d| match *d {
+            Decl::Var(ref name) => Some(name),
+            _ => None
+        }).collect();
+        
+        let consts: Vec<(Pos, String)> = consts.into_iter()
+            .map(|(name, value, radix)| (name.span.start, format!("{} = {}", name.name, format_number(value, radix))))
+            .collect();
+        self.declarations("CONST", &consts);
+        let vars: Vec<(Pos, String)> = vars.into_iter().map(|name| (name.span.start, name.name.to_string())).collect();
+        self.declarations("VAR", &vars);
+        
+        let mut has_procedures = false;
+        for decl in &block.decls {
+            if let Decl::Procedure {ref name, ref block, ..} = *decl {
+                if !self.out.is_empty() {
+                    self.out.push('\n');
+                }
+                self.line_start(Some(name.span.start), 0);
+                self.out.push_str(&format!("PROCEDURE {};\n", name.name));
+                self.block(block, ";");
+                has_procedures = true;
+            }
+        }
+        if has_procedures {
+            self.out.push('\n');
+        }
+        
+        self.statement_line(&block.statement, 0);
+        self.out.push_str(terminator);
+        self.out.push('\n');
+    }
+    
+    fn declarations(&mut self, keyword: &str, decls: &[(Pos, String)]) {
+        if decls.is_empty() {
+            return;
+        }
+        
+        if !self.options.one_decl_per_line {
+            self.line_start(Some(decls[0].0), 0);
+            let items: Vec<&str> = decls.iter().map(|d| d.1.as_str()).collect();
+            self.out.push_str(&format!("{} {};\n", keyword, items.join(", ")));
+            return;
+        }
+        
+        self.line_start(Some(decls[0].0), 0);
+        self.out.push_str(keyword);
+        self.out.push('\n');
+        for (i, &(pos, ref text)) in decls.iter().enumerate() {
+            self.line_start(Some(pos), 1);
+            self.out.push_str(text);
+            self.out.push_str(if i + 1 < decls.len() { ",\n" } else { ";\n" });
+        }
+    }
+    
+    fn statement_line(&mut self, stmt: &Stmt, level: usize) {
+        self.line_start(stmt.span().map(|span| span.start), level);
+        self.statement(stmt, level);
+    }
+    
+    // Writes `stmt` from the current column. Nested lines are indented
+    // relative to `level`.
+    fn statement(&mut self, stmt: &Stmt, level: usize) {
+        match *stmt {
+            Stmt::Assign {ref target, ref value, ..} => {
+                self.out.push_str(&format!("{} := {}", target.name, expr(value)));
+            }
+            Stmt::Call(_, ref ident) => self.out.push_str(&format!("CALL {}", ident.name)),
+            Stmt::Read(_, ref ident) => self.out.push_str(&format!("? {}", ident.name)),
+            Stmt::Write {ref items, newline, ..} => {
+                let items: Vec<String> = items.iter().map(|item| match *item {
+                    WriteItem::Str(_, ref s) => format!("\"{}\"", escape_string(s)),
+                    WriteItem::Expr(ref e) => expr(e),
+                }).collect();
+                self.out.push_str("! ");
+                self.out.push_str(&items.join(", "));
+                if !newline {
+                    self.out.push(',');
+                }
+            }
+            Stmt::BeginEnd(_, ref statements) => {
+                self.out.push_str("BEGIN\n");
+                for (i, s) in statements.iter().enumerate() {
+                    let last = i + 1 == statements.len();
+                    // A trailing `;` already ends the previous line.
+                    if last && *s == Stmt::Empty {
+                        break;
+                    }
+                    self.statement_line(s, level + 1);
+                    if !last {
+                        self.out.push(';');
+                    }
+                    self.out.push('\n');
+                }
+                self.indent(level);
+                self.out.push_str("END");
+            }
+            Stmt::IfThen {ref condition, ref statement, ..} => {
+                self.out.push_str(&format!("IF {} THEN", cond(condition)));
+                self.nested(statement, level);
+            }
+            Stmt::WhileDo {ref condition, ref statement, ..} => {
+                self.out.push_str(&format!("WHILE {} DO", cond(condition)));
+                self.nested(statement, level);
+            }
+            Stmt::Empty | Stmt::Error(_) => {}
+        }
+    }
+    
+    fn nested(&mut self, stmt: &Stmt, level: usize) {
+        if *stmt != Stmt::Empty {
+            self.out.push(' ');
+            self.statement(stmt, level);
+        }
+    }
+}
+
+/// Formats a statement on its own, without comments. Simple statements come
+/// out on a single line.
+pub fn format_stmt(stmt: &Stmt, options: FormatOptions) -> String {
+    let mut f = Formatter {
+        out: String::new(),
+        comments: VecDeque::new(),
+        options
+    };
+    
+    f.statement(stmt, 0);
+    f.out
+}
+
+pub fn format_cond(c: &Cond) -> String {
+    cond(c)
+}
+
+fn expr_prec(e: &Expr) -> u8 {
+    match *e {
+        Expr::Binary {op: BinOp::Add, ..} | Expr::Binary {op: BinOp::Sub, ..} => 1,
+        Expr::Binary {..} => 2,
+        _ => 3
+    }
+}
+
+fn expr(e: &Expr) -> String {
+    expr_at(e, true)
+}
+
+// `leading` is set when `e` starts an expression. The parser takes a sign
+// there as negating the whole first term, so a negated left operand of
+// `*`, `/` or `MOD` needs parentheses.
+fn expr_at(e: &Expr, leading: bool) -> String {
+    match *e {
+        Expr::Number(_, n, radix) => format_number(n, radix),
+        Expr::Ident(ref ident) => ident.name.to_string(),
+        Expr::Negate(_, ref operand) => {
+            if expr_prec(operand) < 3 {
+                format!("-({})", expr_at(operand, true))
+            } else {
+                format!("-{}", expr_at(operand, false))
+            }
+        }
+        Expr::Binary {ref lhs, op, ref rhs, ..} => {
+            let prec = expr_prec(e);
+            let op = match op {
+                BinOp::Add => "+",
+                BinOp::Sub => "-",
+                BinOp::Mul => "*",
+                BinOp::Div => "/",
+                BinOp::Mod => "MOD",
+            };
+            let negated = matches!(**lhs, Expr::Negate(..));
+            let lhs_paren = expr_prec(lhs) < prec || (leading && prec == 2 && negated);
+            let rhs_paren = expr_prec(rhs) <= prec;
+            format!("{} {} {}",
+                paren(expr_at(lhs, leading || lhs_paren), lhs_paren),
+                op,
+                paren(expr_at(rhs, rhs_paren), rhs_paren))
+        }
+        Expr::Call {ref name, ref args, ..} => {
+            let args: Vec<String> = args.iter().map(expr).collect();
+            format!("{}({})", name.name, args.join(", "))
+        }
+    }
+}
+
+fn cond_prec(c: &Cond) -> u8 {
+    match *c {
+        Cond::Logic {op: LogicOp::Or, ..} => 1,
+        Cond::Logic {op: LogicOp::And, ..} => 2,
+        _ => 3
+    }
+}
+
+fn cond(c: &Cond) -> String {
+    match *c {
+        Cond::Odd(_, ref e) => format!("ODD {}", expr(e)),
+        Cond::Compare {ref lhs, op, ref rhs} => {
+            let op = match op {
+                ExOp::Equal => "=",
+                ExOp::NumberSign => "#",
+                ExOp::LessThan => "<",
+                ExOp::LessThanOrEqual => "<=",
+                ExOp::GreaterThan => ">",
+                ExOp::GreaterThanOrEqual => ">=",
+            };
+            format!("{} {} {}", expr(lhs), op, expr(rhs))
+        }
+        Cond::Not(_, ref operand) => format!("NOT {}", paren(cond(operand), cond_prec(operand) < 3)),
+        Cond::Logic {ref lhs, op, ref rhs} => {
+            let prec = cond_prec(c);
+            let op = match op {
+                LogicOp::And => "AND",
+                LogicOp::Or => "OR",
+            };
+            format!("{} {} {}", paren(cond(lhs), cond_prec(lhs) < prec), op, paren(cond(rhs), cond_prec(rhs) <= prec))
+        }
+    }
+}
+
+fn paren(s: String, needed: bool) -> String {
+    if needed {
+        format!("({})", s)
+    } else {
+        s
+    }
+}
+
+#[cfg(test)]
+fn format_source(source: &str, options: FormatOptions) -> String {
+    use lexer::{r_lexer_with, LexerOptions, TokenKind};
+    use parser::parse_program;
+
+    let tokens = r_lexer_with(source, LexerOptions { keep_comments: true, ..LexerOptions::default() }).unwrap();
+    let (comments, tokens): (Vec<Token>, Vec<Token>) = tokens.into_iter()
+        .partition(|t| matches!(t.kind, TokenKind::Comment(_)));
+    let ast = parse_program(&tokens).unwrap();
+
+    format_program(&ast, &comments, options)
+}
+
+#[test]
+fn test_format_program() {
+    let source = "CONST K=0x1F,big=1_000;VAR x,y;
+PROCEDURE p;BEGIN x:=(x+1)*-(y-2);IF NOT(x=1 OR y=2) AND ODD x THEN ! \"x\\t=\", x,;
+WHILE x>0 DO x:=x-(y-1) END;
+{ main } BEGIN CALL p;?y ; END.";
+    let expected = "\
+CONST K = 0x1F, big = 1000;
+VAR x, y;
+
+PROCEDURE p;
+BEGIN
+  x := (x + 1) * -(y - 2);
+  IF NOT (x = 1 OR y = 2) AND ODD x THEN ! \"x\\t=\", x,;
+  WHILE x > 0 DO x := x - (y - 1)
+END;
+
+{ main }
+BEGIN
+  CALL p;
+  ? y;
+END.
+";
+    let formatted = format_source(source, FormatOptions::default());
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted, FormatOptions::default()), formatted);
+}
+
+#[test]
+fn test_format_comments_and_options() {
+    let source = "(* constants *) CONST a = 1, b = 2;
+VAR x; // counter
+BEGIN x := a; { nested { comment } } x := x - -b END.
+// trailing";
+    let options = FormatOptions { indent: 4, one_decl_per_line: true };
+    let expected = "\
+(* constants *)
+CONST
+    a = 1,
+    b = 2;
+VAR
+    x;
+// counter
+BEGIN
+    x := a;
+    { nested { comment } }
+    x := x - -b
+END.
+// trailing
+";
+    let formatted = format_source(source, options);
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted, options), formatted);
+}
+
+#[test]
+fn test_format_keeps_leading_negation_grouped() {
+    let source = "VAR x; BEGIN x := (-x) / 2; x := (-x MOD 3) * ((-x) * 2); x := 1 - (-x) * 2; x := -x / 2 END.";
+    let expected = "\
+VAR x;
+BEGIN
+  x := (-x) / 2;
+  x := (-(x MOD 3)) * ((-x) * 2);
+  x := 1 - -x * 2;
+  x := -(x / 2)
+END.
+";
+    let formatted = format_source(source, FormatOptions::default());
+    assert_eq!(formatted, expected);
+    assert_eq!(format_source(&formatted, FormatOptions::default()), formatted);
+}
@@ -0,0 +1,308 @@
+//! Random well-formed PL/0 programs, for fuzzing the parser and for
+//! differential testing of the execution engines.
+//!
+//! Every generated program terminates:
+//! - Each `WHILE` counts a variable of its own up to a small bound.
+//! - A procedure only calls procedures declared before it, so calls never
+//!   recurse.
+//!
+//! Every name is unique and declared before it is used. Loop counters are
+//! never assigned outside their loop. Divisors are mostly nonzero constants,
+//! but a program may still stop with a runtime error such as a division by
+//! zero or an overflow. Programs never read input.
+
+use ast::*;
+use lexer::{Pos, Radix};
+use symbol::Symbol;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GeneratorOptions {
+    /// How deeply procedures nest.
+    pub max_nesting: usize,
+    /// The most constants, variables, procedures or statements in a block.
+    pub max_items: usize,
+    /// How deeply statements, expressions and conditions nest.
+    pub max_depth: usize,
+    /// The most iterations of any loop.
+    pub max_iterations: i64,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> Self {
+        GeneratorOptions {
+            max_nesting: 2,
+            max_items: 4,
+            max_depth: 3,
+            max_iterations: 4
+        }
+    }
+}
+
+/// The same seed and options always give the same program.
+pub fn generate(seed: u64, options: GeneratorOptions) -> Program {
+    let mut g = Generator {
+        rng: Rng(seed),
+        options: GeneratorOptions {
+            max_items: options.max_items.max(1),
+            max_iterations: options.max_iterations.max(1),
+            ..options
+        },
+        names: 0
+    };
+    
+    Program { block: g.block(&Scope::default(), 0) }
+}
+
+// splitmix64.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+    
+    // In `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+    
+    fn pick<T: Clone>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())].clone()
+    }
+}
+
+// The names usable at some point of the program.
+#[derive(Clone, Default)]
+struct Scope {
+    readable: Vec<Symbol>,
+    writable: Vec<Symbol>,
+    callable: Vec<Symbol>,
+}
+
+struct Generator {
+    rng: Rng,
+    options: GeneratorOptions,
+    names: usize,
+}
+
+// Generated programs have no source, so every node is placed at 1:1.
+const SPAN: Span = Span { start: Pos { line: 1, col: 1 }, end: Pos { line: 1, col: 1 } };
+
+fn ident(name: &Symbol) -> Ident {
+    Ident { name: name.clone(), span: SPAN }
+}
+
+impl Generator {
+    fn name(&mut self, prefix: &str) -> Symbol {
+        self.names += 1;
+        Symbol::intern(&format!("{}{}", prefix, self.names))
+    }
+    
+    fn number(&mut self) -> Expr {
+        let radix = match self.rng.below(8) {
+            0 => Radix::Hex,
+            1 => Radix::Binary,
+            _ => Radix::Decimal
+        };
+        Expr::Number(SPAN, self.rng.below(21) as i64, radix)
+    }
+    
+    fn block(&mut self, outer: &Scope, nesting: usize) -> Block {
+        let mut scope = outer.clone();
+        let mut decls = Vec::new();
+        
+        for _ in 0..self.rng.below(self.options.max_items + 1) {
+            let name = self.name("k");
+            let value = self.rng.below(100) as i64;
+            let radix = if self.rng.chance(20) { Radix::Hex } else { Radix::Decimal };
+            decls.push(Decl::Const { span: SPAN, name: ident(&name), value, radix });
+            scope.readable.push(name);
+        }
+        for _ in 0..1 + self.rng.below(self.options.max_items) {
+            let name = self.name("v");
+            decls.push(Decl::Var(ident(&name)));
+            scope.readable.push(name.clone());
+            scope.writable.push(name);
+        }
+        let vars_end = decls.len();
+        
+        if nesting < self.options.max_nesting {
+            for _ in 0..self.rng.below(self.options.max_items / 2 + 1) {
+                let name = self.name("p");
+                let block = self.block(&scope, nesting + 1);
+                decls.push(Decl::Procedure { span: SPAN, name: ident(&name), block: Box::new(block) });
+                scope.callable.push(name);
+            }
+        }
+        
+        // Loop counters are only known once the statements are generated.
+        let mut counters = Vec::new();
+        let statement = self.statement_list(&scope, 0, &mut counters);
+        let counters = counters.iter().map(|c| Decl::Var(ident(c)));
+        decls.splice(vars_end..vars_end, counters);
+        
+        Block { span: Some(SPAN), decls, statement }
+    }
+    
+    fn statement_list(&mut self, scope: &Scope, depth: usize, counters: &mut Vec<Symbol>) -> Stmt {
+        let statements = (0..1 + self.rng.below(self.options.max_items))
+            .map(|_| self.statement(scope, depth, counters))
+            .collect();
+        Stmt::BeginEnd(SPAN, statements)
+    }
+    
+    fn statement(&mut self, scope: &Scope, depth: usize, counters: &mut Vec<Symbol>) -> Stmt {
+        let nested = depth < self.options.max_depth;
+        
+        match self.rng.below(if nested { 10 } else { 5 }) {
+            0 | 1 => {
+                let target = ident(&self.rng.pick(&scope.writable));
+                Stmt::Assign { span: SPAN, target, value: self.expr(scope, 0) }
+            }
+            2 if !scope.callable.is_empty() => Stmt::Call(SPAN, ident(&self.rng.pick(&scope.callable))),
+            2 | 3 => {
+                let items = (0..1 + self.rng.below(3)).map(|_| {
+                    if self.rng.chance(30) {
+                        WriteItem::Str(SPAN, format!("s{} ", self.rng.below(10)))
+                    } else {
+                        WriteItem::Expr(self.expr(scope, 0))
+                    }
+                }).collect();
+                Stmt::Write { span: SPAN, items, newline: self.rng.chance(80) }
+            }
+            4 => Stmt::Empty,
+            5 | 6 => Stmt::IfThen {
+                span: SPAN,
+                condition: self.cond(scope, 0),
+                statement: Box::new(self.statement(scope, depth + 1, counters))
+            },
+            7 => self.statement_list(scope, depth + 1, counters),
+            _ => self.bounded_loop(scope, depth, counters),
+        }
+    }
+    
+    // `i := 0; WHILE i < n [AND cond] DO BEGIN ...; i := i + 1 END`
+    fn bounded_loop(&mut self, scope: &Scope, depth: usize, counters: &mut Vec<Symbol>) -> Stmt {
+        let counter = self.name("i");
+        counters.push(counter.clone());
+        
+        let mut condition = Cond::Compare {
+            lhs: Expr::Ident(ident(&counter)),
+            op: ExOp::LessThan,
+            rhs: Expr::Number(SPAN, 1 + self.rng.below(self.options.max_iterations as usize) as i64, Radix::Decimal)
+        };
+        if self.rng.chance(30) {
+            condition = Cond::Logic {
+                lhs: Box::new(condition),
+                op: LogicOp::And,
+                rhs: Box::new(self.cond(scope, 0))
+            };
+        }
+        
+        let mut inner = scope.clone();
+        inner.readable.push(counter.clone());
+        let mut body = match self.statement_list(&inner, depth + 1, counters) {
+            Stmt::BeginEnd(_, statements) => statements,
+            _ => unreachable!()
+        };
+        body.push(Stmt::Assign {
+            span: SPAN,
+            target: ident(&counter),
+            value: Expr::Binary {
+                lhs: Box::new(Expr::Ident(ident(&counter))),
+                op: BinOp::Add,
+                pos: SPAN.start,
+                rhs: Box::new(Expr::Number(SPAN, 1, Radix::Decimal))
+            }
+        });
+        
+        Stmt::BeginEnd(SPAN, vec![
+            Stmt::Assign { span: SPAN, target: ident(&counter), value: Expr::Number(SPAN, 0, Radix::Decimal) },
+            Stmt::WhileDo { span: SPAN, condition, statement: Box::new(Stmt::BeginEnd(SPAN, body)) },
+        ])
+    }
+    
+    fn expr(&mut self, scope: &Scope, depth: usize) -> Expr {
+        if depth >= self.options.max_depth || self.rng.chance(40) {
+            return if self.rng.chance(50) {
+                Expr::Ident(ident(&self.rng.pick(&scope.readable)))
+            } else {
+                self.number()
+            };
+        }
+        
+        if self.rng.chance(15) {
+            return Expr::Negate(SPAN.start, Box::new(self.expr(scope, depth + 1)));
+        }
+        let op = self.rng.pick(&[BinOp::Add, BinOp::Sub, BinOp::Mul, BinOp::Div, BinOp::Mod]);
+        let lhs = self.expr(scope, depth + 1);
+        // Mostly a nonzero divisor, or few programs would run to the end.
+        let rhs = match op {
+            BinOp::Div | BinOp::Mod if self.rng.chance(90) => Expr::Number(SPAN, 1 + self.rng.below(20) as i64, Radix::Decimal),
+            _ => self.expr(scope, depth + 1)
+        };
+        Expr::Binary { lhs: Box::new(lhs), op, pos: SPAN.start, rhs: Box::new(rhs) }
+    }
+    
+    fn cond(&mut self, scope: &Scope, depth: usize) -> Cond {
+        if depth >= self.options.max_depth || self.rng.chance(60) {
+            return if self.rng.chance(20) {
+                Cond::Odd(SPAN.start, self.expr(scope, depth + 1))
+            } else {
+                let op = self.rng.pick(&[
+                    ExOp::Equal,
+                    ExOp::NumberSign,
+                    ExOp::LessThan,
+                    ExOp::LessThanOrEqual,
+                    ExOp::GreaterThan,
+                    ExOp::GreaterThanOrEqual,
+                ]);
+                Cond::Compare { lhs: self.expr(scope, depth + 1), op, rhs: self.expr(scope, depth + 1) }
+            };
+        }
+        
+        if self.rng.chance(25) {
+            return Cond::Not(SPAN.start, Box::new(self.cond(scope, depth + 1)));
+        }
+        let op = if self.rng.chance(50) { LogicOp::And } else { LogicOp::Or };
+        Cond::Logic {
+            lhs: Box::new(self.cond(scope, depth + 1)),
+            op,
+            rhs: Box::new(self.cond(scope, depth + 1))
+        }
+    }
+}
+
+#[test]
+fn test_generated_programs_round_trip_and_terminate() {
+    use format::{format_program, FormatOptions};
+    use interpreter::{Interpreter, MemoryIo};
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    for seed in 0..200 {
+        let program = generate(seed, GeneratorOptions::default());
+        let source = format_program(&program, &[], FormatOptions::default());
+
+        let parsed = match parse_program(&r_lexer(&source).unwrap()) {
+            Ok(parsed) => parsed,
+            Err(failure) => panic!("seed {}: {:?}\n{}", seed, failure.errors, source)
+        };
+        assert_eq!(format_program(&parsed, &[], FormatOptions::default()), source, "seed {}", seed);
+
+        // Runtime errors are fine; panics and endless loops are not.
+        let _ = Interpreter::new(parsed).run_with(&mut MemoryIo::new(""));
+    }
+
+    let options = GeneratorOptions { max_items: 2, ..GeneratorOptions::default() };
+    assert_eq!(generate(7, options), generate(7, options));
+    assert!(generate(7, options) != generate(8, options));
+}
@@ -0,0 +1,341 @@
+//! Graphviz (DOT) output for the AST and for control flow.
+
+use ast::*;
+use format::{format_cond, format_stmt, FormatOptions};
+use lexer::format_number;
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn span_label(span: Option<Span>) -> String {
+    match span {
+        Some(span) if span.start == span.end => format!("{}", span.start),
+        Some(span) => format!("{}-{}", span.start, span.end),
+        None => "no position".to_string()
+    }
+}
+
+/// The AST as a tree, one box per node labelled with its kind, details and
+/// span.
+pub fn ast_to_dot(program: &Program) -> String {
+    let mut g = AstGraph { out: String::new(), next: 0 };
+    g.out.push_str("digraph ast {\n  node [shape=box, fontname=\"monospace\"];\n");
+    
+    let root = g.node("Program", "", None);
+    let block = g.block(&program.block);
+    g.edge(root, block);
+    
+    g.out.push_str("}\n");
+    g.out
+}
+
+struct AstGraph {
+    out: String,
+    next: usize,
+}
+
+impl AstGraph {
+    fn node(&mut self, kind: &str, detail: &str, span: Option<Span>) -> usize {
+        let id = self.next;
+        self.next += 1;
+        
+        let mut label = kind.to_string();
+        if !detail.is_empty() {
+            label.push(' ');
+            label.push_str(detail);
+        }
+        if span.is_some() {
+            label.push('\n');
+            label.push_str(&span_label(span));
+        }
+        self.out.push_str(&format!("  n{} [label=\"{}\"];\n", id, escape(&label)));
+        id
+    }
+    
+    fn edge(&mut self, from: usize, to: usize) {
+        self.out.push_str(&format!("  n{} -> n{};\n", from, to));
+    }
+    
+    fn error(&mut self, e: &SyntaxError) -> usize {
+        self.node("Error", &format!("expected {}", e.expected), e.found.as_ref().map(|found| found.0))
+    }
+    
+    fn block(&mut self, block: &Block) -> usize {
+        let id = self.node("Block", "", block.span);
+        
+        for decl in &block.decls {
+            let child = match *decl {
+                Decl::Const {span, ref name, value, radix} => {
+                    let detail = format!("{} = {}", name.name, format_number(value, radix));
+                    self.node("Const", &detail, Some(span))
+                }
+                Decl::Var(ref name) => self.node("Var", &name.name, Some(name.span)),
+                Decl::Procedure {span, ref name, ref block} => {
+                    let proc_id = self.node("Procedure", &name.name, Some(span));
+                    let block_id = self.block(block);
+                    self.edge(proc_id, block_id);
+                    proc_id
+                }
+                Decl::Error(ref e) => self.error(e),
+            };
+            self.edge(id, child);
+        }
+        let body = self.stmt(&block.statement);
+        self.edge(id, body);
+        id
+    }
+    
+    fn stmt(&mut self, stmt: &Stmt) -> usize {
+        let span = stmt.span();
+        
+        match *stmt {
+            Stmt::Assign {ref target, ref value, ..} => {
+                let id = self.node("Assign", &target.name, span);
+                let value = self.expr(value);
+                self.edge(id, value);
+                id
+            }
+            Stmt::Call(_, ref ident) => self.node("Call", &ident.name, span),
+            Stmt::Read(_, ref ident) => self.node("Read", &ident.name, span),
+            Stmt::Write {ref items, newline, ..} => {
+                let id = self.node("Write", if newline { "" } else { "(no newline)" }, span);
+                for item in items {
+                    let child = match *item {
+                        WriteItem::Str(span, ref s) => self.node("Str", &format!("{:?}", s), Some(span)),
+                        WriteItem::Expr(ref e) => self.expr(e),
+                    };
+                    self.edge(id, child);
+                }
+                id
+            }
+            Stmt::BeginEnd(_, ref statements) => {
+                let id = self.node("BeginEnd", "", span);
+                for s in statements {
+                    let child = self.stmt(s);
+                    self.edge(id, child);
+                }
+                id
+            }
+            Stmt::IfThen {ref condition, ref statement, ..} | Stmt::WhileDo {ref condition, ref statement, ..} => {
+                let kind = if let Stmt::IfThen {..} = *stmt { "IfThen" } else { "WhileDo" };
+                let id = self.node(kind, "", span);
+                let c = self.cond(condition);
+                self.edge(id, c);
+                let s = self.stmt(statement);
+                self.edge(id, s);
+                id
+            }
+            Stmt::Empty => self.node("Empty", "", span),
+            Stmt::Error(ref e) => self.error(e),
+        }
+    }
+    
+    fn cond(&mut self, cond: &Cond) -> usize {
+        let span = Some(cond.span());
+        
+        match *cond {
+            Cond::Odd(_, ref e) => {
+                let id = self.node("Odd", "", span);
+                let child = self.expr(e);
+                self.edge(id, child);
+                id
+            }
+            Cond::Compare {ref lhs, op, ref rhs} => {
+                let id = self.node("Compare", &format!("{:?}", op), span);
+                let l = self.expr(lhs);
+                self.edge(id, l);
+                let r = self.expr(rhs);
+                self.edge(id, r);
+                id
+            }
+            Cond::Not(_, ref c) => {
+                let id = self.node("Not", "", span);
+                let child = self.cond(c);
+                self.edge(id, child);
+                id
+            }
+            Cond::Logic {ref lhs, op, ref rhs} => {
+                let id = self.node("Logic", &format!("{:?}", op), span);
+                let l = self.cond(lhs);
+                self.edge(id, l);
+                let r = self.cond(rhs);
+                self.edge(id, r);
+                id
+            }
+        }
+    }
+    
+    fn expr(&mut self, expr: &Expr) -> usize {
+        let span = Some(expr.span());
+        
+        match *expr {
+            Expr::Number(_, n, radix) => self.node("Number", &format_number(n, radix), span),
+            Expr::Ident(ref ident) => self.node("Ident", &ident.name, span),
+            Expr::Negate(_, ref e) => {
+                let id = self.node("Negate", "", span);
+                let child = self.expr(e);
+                self.edge(id, child);
+                id
+            }
+            Expr::Binary {ref lhs, op, ref rhs, ..} => {
+                let id = self.node("Binary", &format!("{:?}", op), span);
+                let l = self.expr(lhs);
+                self.edge(id, l);
+                let r = self.expr(rhs);
+                self.edge(id, r);
+                id
+            }
+            Expr::Call {ref name, ref args, ..} => {
+                let id = self.node("Call", &name.name, span);
+                for arg in args {
+                    let child = self.expr(arg);
+                    self.edge(id, child);
+                }
+                id
+            }
+        }
+    }
+}
+
+/// One control-flow graph per procedure and one for the main program, each
+/// in its own cluster. Every simple statement and every `IF`/`WHILE`
+/// condition is a node; condition nodes have `true` and `false` edges.
+pub fn cfg_to_dot(program: &Program) -> String {
+    let mut g = CfgGraph { out: String::new(), next: 0, clusters: 0 };
+    g.out.push_str("digraph cfg {\n  node [shape=box, fontname=\"monospace\"];\n");
+    g.block("main", "", &program.block);
+    g.out.push_str("}\n");
+    g.out
+}
+
+struct CfgGraph {
+    out: String,
+    next: usize,
+    clusters: usize,
+}
+
+// A node whose outgoing edge is not connected yet, with the edge's label.
+type Exit = (usize, Option<&'static str>);
+
+impl CfgGraph {
+    fn node(&mut self, label: &str, shape: &str) -> usize {
+        let id = self.next;
+        self.next += 1;
+        self.out.push_str(&format!("    n{} [label=\"{}\", shape={}];\n", id, escape(label), shape));
+        id
+    }
+    
+    fn connect(&mut self, exits: &[Exit], to: usize) {
+        for &(from, label) in exits {
+            match label {
+                Some(label) => self.out.push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", from, to, label)),
+                None => self.out.push_str(&format!("    n{} -> n{};\n", from, to)),
+            }
+        }
+    }
+    
+    // `prefix` is the dotted path of the enclosing procedures.
+    fn block(&mut self, name: &str, prefix: &str, block: &Block) {
+        self.out.push_str(&format!("  subgraph cluster_{} {{\n    label=\"{}\";\n", self.clusters, escape(name)));
+        self.clusters += 1;
+        
+        let entry = self.node("entry", "oval");
+        let exits = self.stmt(&block.statement, vec![(entry, None)]);
+        let exit = self.node("exit", "oval");
+        self.connect(&exits, exit);
+        self.out.push_str("  }\n");
+        
+        for decl in &block.decls {
+            if let Decl::Procedure {name: ref proc_name, ref block, ..} = *decl {
+                let path = if prefix.is_empty() { proc_name.name.to_string() } else { format!("{}.{}", prefix, proc_name.name) };
+                self.block(&path, &path, block);
+            }
+        }
+    }
+    
+    fn stmt(&mut self, stmt: &Stmt, entries: Vec<Exit>) -> Vec<Exit> {
+        match *stmt {
+            Stmt::Empty | Stmt::Error(_) => entries,
+            Stmt::BeginEnd(_, ref statements) => {
+                statements.iter().fold(entries, |entries, s| self.stmt(s, entries))
+            }
+            Stmt::IfThen {ref condition, ref statement, ..} => {
+                let c = self.node(&format!("IF {}", format_cond(condition)), "diamond");
+                self.connect(&entries, c);
+                let mut exits = self.stmt(statement, vec![(c, Some("true"))]);
+                exits.push((c, Some("false")));
+                exits
+            }
+            Stmt::WhileDo {ref condition, ref statement, ..} => {
+                let c = self.node(&format!("WHILE {}", format_cond(condition)), "diamond");
+                self.connect(&entries, c);
+                let body_exits = self.stmt(statement, vec![(c, Some("true"))]);
+                self.connect(&body_exits, c);
+                vec![(c, Some("false"))]
+            }
+            _ => {
+                let id = self.node(&format_stmt(stmt, FormatOptions::default()), "box");
+                self.connect(&entries, id);
+                vec![(id, None)]
+            }
+        }
+    }
+}
+
+#[test]
+fn test_ast_to_dot() {
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    let dot = ast_to_dot(&parse_program(&r_lexer("VAR x;\nBEGIN x := 1;\n! \"a\\\"b\", x + 2;\nIF NOT (x) > 10 THEN ? x END.").unwrap()).unwrap());
+
+    assert!(dot.starts_with("digraph ast {\n"));
+    assert!(dot.contains("n1 [label=\"Block\\n1:1-4:28\"];"));
+    assert!(dot.contains("[label=\"Var x\\n1:5\"];"));
+    assert!(dot.contains("[label=\"Assign x\\n2:7-2:12\"];"));
+    assert!(dot.contains("[label=\"Write\\n3:1-3:15\"];"));
+    assert!(dot.contains("[label=\"Str \\\"a\\\\\\\"b\\\"\\n3:3-3:8\"];"));
+    assert!(dot.contains("[label=\"Binary Add\\n3:11-3:15\"];"));
+    assert!(dot.contains("[label=\"IfThen\\n4:1-4:24\"];"));
+    assert!(dot.contains("[label=\"Compare GreaterThan\\n4:9-4:15\"];"));
+    assert!(dot.contains("[label=\"Number 10\\n4:14-4:15\"];"));
+    assert_eq!(dot.matches("[label=").count(), dot.matches(" -> ").count() + 1);
+}
+
+#[test]
+fn test_cfg_to_dot() {
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    let dot = cfg_to_dot(&parse_program(&r_lexer("VAR x; PROCEDURE p; ; BEGIN WHILE x < 3 DO BEGIN IF ODD x THEN CALL p; x := x + 1 END END.").unwrap()).unwrap());
+
+    let expected = "\
+digraph cfg {
+  node [shape=box, fontname=\"monospace\"];
+  subgraph cluster_0 {
+    label=\"main\";
+    n0 [label=\"entry\", shape=oval];
+    n1 [label=\"WHILE x < 3\", shape=diamond];
+    n0 -> n1;
+    n2 [label=\"IF ODD x\", shape=diamond];
+    n1 -> n2 [label=\"true\"];
+    n3 [label=\"CALL p\", shape=box];
+    n2 -> n3 [label=\"true\"];
+    n4 [label=\"x := x + 1\", shape=box];
+    n3 -> n4;
+    n2 -> n4 [label=\"false\"];
+    n4 -> n1;
+    n5 [label=\"exit\", shape=oval];
+    n1 -> n5 [label=\"false\"];
+  }
+  subgraph cluster_1 {
+    label=\"p\";
+    n6 [label=\"entry\", shape=oval];
+    n7 [label=\"exit\", shape=oval];
+    n6 -> n7;
+  }
+}
+";
+    assert_eq!(dot, expected);
+}
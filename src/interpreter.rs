@@ -1,243 +1,780 @@
-use parser::*;
-use std::collections::HashMap;
+use ast::*;
+use lexer::{IntWidth, Pos};
+use std::collections::{HashMap, VecDeque};
+use symbol::Symbol;
+use std::fmt;
 use std::io;
+use std::io::Write;
+use std::time::{Duration, Instant};
 
-pub struct Interpreter<'a> {
-    ast: AstNode<'a>,
+// One activation of a block. `parent` is the frame of the block the
+// procedure was declared in, whose names are visible from this one.
+struct Frame<'a> {
+    vars: HashMap<Symbol, i64>,
+    procedures: HashMap<Symbol, &'a Block>,
+    parent: Option<usize>,
 }
 
-impl<'a> Interpreter<'a> {
-    pub fn new(ast: AstNode<'a>) -> Self {
+impl<'a> Frame<'a> {
+    fn new(parent: Option<usize>) -> Self {
+        Frame {
+            vars: HashMap::new(),
+            procedures: HashMap::new(),
+            parent
+        }
+    }
+}
+
+enum Task<'a> {
+    Execute(&'a Stmt),
+    // Leaves the frame of a finished call.
+    Return,
+}
+
+/// What to do with a result that does not fit in the integer width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    Checked,
+    Wrapping,
+    Saturating
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunMode {
+    pub width: IntWidth,
+    pub overflow: Overflow,
+}
+
+impl Default for RunMode {
+    fn default() -> Self {
+        RunMode {
+            width: IntWidth::I32,
+            overflow: Overflow::Checked
+        }
+    }
+}
+
+impl RunMode {
+    fn bounds(&self) -> (i128, i128) {
+        match self.width {
+            IntWidth::I32 => (i32::MIN as i128, i32::MAX as i128),
+            IntWidth::I64 => (i64::MIN as i128, i64::MAX as i128),
+        }
+    }
+    
+    // Brings an exact result into the configured width.
+    fn fit(&self, v: i128, pos: Pos) -> Result<i64, RuntimeError> {
+        let (min, max) = self.bounds();
+        if v >= min && v <= max {
+            return Ok(v as i64);
+        }
+        
+        match self.overflow {
+            Overflow::Checked => Err(RuntimeError::Overflow(pos)),
+            Overflow::Wrapping => match self.width {
+                IntWidth::I32 => Ok(v as i32 as i64),
+                IntWidth::I64 => Ok(v as i64),
+            },
+            Overflow::Saturating => Ok(if v < min { min as i64 } else { max as i64 }),
+        }
+    }
+    
+    fn add(&self, lhs: i64, rhs: i64, pos: Pos) -> Result<i64, RuntimeError> {
+        self.fit(lhs as i128 + rhs as i128, pos)
+    }
+    
+    fn sub(&self, lhs: i64, rhs: i64, pos: Pos) -> Result<i64, RuntimeError> {
+        self.fit(lhs as i128 - rhs as i128, pos)
+    }
+    
+    fn mul(&self, lhs: i64, rhs: i64, pos: Pos) -> Result<i64, RuntimeError> {
+        self.fit(lhs as i128 * rhs as i128, pos)
+    }
+    
+    fn div(&self, lhs: i64, rhs: i64, pos: Pos) -> Result<i64, RuntimeError> {
+        if rhs == 0 {
+            return Err(RuntimeError::DivisionByZero(pos));
+        }
+        self.fit(lhs as i128 / rhs as i128, pos)
+    }
+    
+    fn rem(&self, lhs: i64, rhs: i64, pos: Pos) -> Result<i64, RuntimeError> {
+        if rhs == 0 {
+            return Err(RuntimeError::DivisionByZero(pos));
+        }
+        self.fit(lhs as i128 % rhs as i128, pos)
+    }
+}
+
+/// Bounds on a run, for programs that may never stop. A run that goes past
+/// one stops with `RuntimeError::LimitExceeded` at the statement where it
+/// did. By default only the call depth is limited, to 1000.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The most statements to execute, counting each pass of a loop and not
+    /// counting empty statements.
+    pub max_steps: Option<u64>,
+    /// The most procedure calls in progress at once.
+    pub max_call_depth: Option<usize>,
+    /// The longest a run may take. The clock is only read on loop
+    /// iterations and procedure calls.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_steps: None,
+            max_call_depth: Some(1000),
+            timeout: None
+        }
+    }
+}
+
+impl Limits {
+    /// For programs that are trusted to stop.
+    pub fn unlimited() -> Self {
+        Limits {
+            max_steps: None,
+            max_call_depth: None,
+            timeout: None
+        }
+    }
+}
+
+/// Which of the `Limits` a run went past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    CallDepth,
+    Time
+}
+
+/// Where `?` reads from and `!` writes to. An error stops the program with
+/// `RuntimeError::Io`.
+pub trait Io {
+    /// The next line of input, `None` once there is none.
+    fn read_line(&mut self) -> io::Result<Option<String>>;
+    /// What a `!` statement prints, with its newline if it has one.
+    fn write(&mut self, text: &str) -> io::Result<()>;
+}
+
+/// The process's stdin and stdout.
+pub struct StdIo;
+
+impl Io for StdIo {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line)? {
+            0 => Ok(None),
+            _ => Ok(Some(line))
+        }
+    }
+    
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        let mut stdout = io::stdout();
+        stdout.write_all(text.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+/// Input given up front and output collected in a string.
+#[derive(Debug, Default)]
+pub struct MemoryIo {
+    input: VecDeque<String>,
+    pub output: String,
+}
+
+impl MemoryIo {
+    pub fn new(input: &str) -> Self {
+        MemoryIo {
+            input: input.lines().map(str::to_string).collect(),
+            output: String::new()
+        }
+    }
+}
+
+impl Io for MemoryIo {
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        Ok(self.input.pop_front())
+    }
+    
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        self.output.push_str(text);
+        Ok(())
+    }
+}
+
+type NativeProcedure = Box<dyn Fn(&mut dyn Io) -> Result<(), String> + Send + Sync>;
+type NativeFunction = Box<dyn Fn(&[i64]) -> Result<i64, String> + Send + Sync>;
+
+/// Procedures and functions provided by the host. They are visible
+/// everywhere, unless a declaration in the program hides them. An `Err`
+/// from one stops the program with `RuntimeError::Native`.
+#[derive(Default)]
+pub struct Natives {
+    procedures: HashMap<Symbol, NativeProcedure>,
+    functions: HashMap<Symbol, (usize, NativeFunction)>,
+}
+
+impl Natives {
+    pub fn new() -> Self {
+        Natives::default()
+    }
+    
+    /// Makes `CALL name` run `procedure`.
+    pub fn procedure<F>(&mut self, name: &str, procedure: F) -> &mut Self
+        where F: Fn(&mut dyn Io) -> Result<(), String> + Send + Sync + 'static
+    {
+        self.procedures.insert(Symbol::intern(name), Box::new(procedure));
+        self
+    }
+    
+    /// Makes `name(a, b, ...)` with `arity` arguments an expression whose
+    /// value is what `function` returns for them.
+    pub fn function<F>(&mut self, name: &str, arity: usize, function: F) -> &mut Self
+        where F: Fn(&[i64]) -> Result<i64, String> + Send + Sync + 'static
+    {
+        self.functions.insert(Symbol::intern(name), (arity, Box::new(function)));
+        self
+    }
+    
+    pub fn has_procedure(&self, name: &Symbol) -> bool {
+        self.procedures.contains_key(name)
+    }
+    
+    /// The number of arguments of function `name`, if there is one.
+    pub fn function_arity(&self, name: &Symbol) -> Option<usize> {
+        self.functions.get(name).map(|f| f.0)
+    }
+}
+
+impl fmt::Debug for Natives {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Natives")
+            .field("procedures", &self.procedures.keys().collect::<Vec<_>>())
+            .field("functions", &self.functions.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    Overflow(Pos),
+    DivisionByZero(Pos),
+    UndefinedVariable(Ident),
+    UndefinedProcedure(Ident),
+    UndefinedFunction(Ident),
+    WrongArgumentCount {function: Ident, expected: usize, found: usize},
+    // A native procedure or function failed.
+    Native(Ident, String),
+    // `?` got a line that is not a number in range, or no line at all.
+    InvalidInput(Pos),
+    // The program still holds an error node from parsing.
+    SyntaxError(SyntaxError),
+    // At the statement where the run was stopped.
+    LimitExceeded(Limit, Pos),
+    // Reading input or writing output failed, e.g. with `BrokenPipe` once
+    // whatever reads stdout has exited.
+    Io(Pos, io::ErrorKind, String),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RuntimeError::Overflow(pos) => write!(f, "{}: arithmetic overflow", pos),
+            RuntimeError::DivisionByZero(pos) => write!(f, "{}: division by zero", pos),
+            RuntimeError::UndefinedVariable(ref ident) => write!(f, "{}: undefined variable `{}`", ident.span.start, ident.name),
+            RuntimeError::UndefinedProcedure(ref ident) => write!(f, "{}: undefined procedure `{}`", ident.span.start, ident.name),
+            RuntimeError::UndefinedFunction(ref ident) => write!(f, "{}: undefined function `{}`", ident.span.start, ident.name),
+            RuntimeError::WrongArgumentCount {ref function, expected, found} => {
+                let plural = if expected == 1 { "" } else { "s" };
+                write!(f, "{}: `{}` takes {} argument{} but got {}", function.span.start, function.name, expected, plural, found)
+            }
+            RuntimeError::Native(ref ident, ref message) => write!(f, "{}: `{}` failed: {}", ident.span.start, ident.name, message),
+            RuntimeError::InvalidInput(pos) => write!(f, "{}: input is not a number", pos),
+            RuntimeError::SyntaxError(ref e) => write!(f, "cannot run a program with syntax errors: {}", e),
+            RuntimeError::LimitExceeded(limit, pos) => {
+                let what = match limit {
+                    Limit::Steps => "too many statements executed",
+                    Limit::CallDepth => "procedure calls nested too deeply",
+                    Limit::Time => "out of time",
+                };
+                write!(f, "{}: stopped: {}", pos, what)
+            }
+            RuntimeError::Io(pos, _, ref message) => write!(f, "{}: input/output failed: {}", pos, message),
+        }
+    }
+}
+
+struct Env<'a> {
+    call_stack: Vec<Frame<'a>>,
+    mode: RunMode,
+    natives: &'a Natives,
+    io: &'a mut dyn Io,
+    limits: Limits,
+    steps: u64,
+    deadline: Option<Instant>,
+}
+
+impl<'a> Env<'a> {
+    fn count_step(&mut self, pos: Pos) -> Result<(), RuntimeError> {
+        self.steps += 1;
+        if self.limits.max_steps.is_some_and(|max| self.steps > max) {
+            return Err(RuntimeError::LimitExceeded(Limit::Steps, pos));
+        }
+        Ok(())
+    }
+    
+    fn check_deadline(&self, pos: Pos) -> Result<(), RuntimeError> {
+        if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Err(RuntimeError::LimitExceeded(Limit::Time, pos));
+        }
+        Ok(())
+    }
+}
+
+pub struct Interpreter {
+    program: Program,
+    mode: RunMode,
+    natives: Natives,
+    limits: Limits,
+}
+
+impl Interpreter {
+    pub fn new(program: Program) -> Self {
         Interpreter {
-            ast: ast
+            program,
+            mode: RunMode::default(),
+            natives: Natives::default(),
+            limits: Limits::default()
         }
     }
     
-    pub fn run(&self) {
-        // println!("run called");
-        Self::visit(&self.ast);
+    pub fn set_mode(&mut self, mode: RunMode) {
+        self.mode = mode;
     }
     
-    fn visit(node: &AstNode<'a>) {
-        let variables: HashMap<String, i32> = HashMap::new();
-        let p_map: HashMap<String, &AstNode<'a>> = HashMap::new();
-        
-        let mut call_stack = vec![(variables, p_map)];
+    pub fn set_natives(&mut self, natives: Natives) {
+        self.natives = natives;
+    }
+    
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+    
+    pub fn natives(&self) -> &Natives {
+        &self.natives
+    }
+    
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+    
+    pub fn run(&self) -> Result<(), RuntimeError> {
+        self.run_with(&mut StdIo)
+    }
+    
+    pub fn run_with(&self, io: &mut dyn Io) -> Result<(), RuntimeError> {
+        let mut env = Env {
+            call_stack: vec![Frame::new(None)],
+            mode: self.mode,
+            natives: &self.natives,
+            io,
+            limits: self.limits,
+            steps: 0,
+            deadline: self.limits.timeout.map(|timeout| Instant::now() + timeout)
+        };
         
-        Self::visit_impl(node, &mut call_stack);
+        Self::declare(&self.program.block, &mut env.call_stack[0]);
+        Self::execute(&self.program.block.statement, &mut env)
     }
     
-    fn visit_impl<'b>(node: &'b AstNode<'a>, call_stack: &mut Vec<(HashMap<String, i32>, HashMap<String, &'b AstNode<'a>>)>) -> Option<i32> {
-        match *node {
-            AstNode::Number(num) => Some(num),
-            AstNode::Ident(ref s) => {
-                let v = Self::get_var_entry(call_stack, s.to_string());
-                Some(*v)
+    fn declare<'a>(block: &'a Block, frame: &mut Frame<'a>) {
+        for decl in &block.decls {
+            match *decl {
+                Decl::Const {ref name, value, ..} => {
+                    frame.vars.insert(name.name.clone(), value);
+                }
+                Decl::Var(ref name) => {
+                    frame.vars.insert(name.name.clone(), 0);
+                }
+                Decl::Procedure {ref name, ref block, ..} => {
+                    frame.procedures.insert(name.name.clone(), block);
+                }
+                Decl::Error(_) => {}
             }
-            AstNode::Factor(ref n) => {
-                Self::visit_impl(n, call_stack)
+        }
+    }
+    
+    // Statements still to run are kept on `tasks` rather than on the native
+    // stack, so neither nesting nor calls can overflow it. Only expressions
+    // and conditions recurse, as deeply as the parser allows them to nest.
+    fn execute<'a>(stmt: &'a Stmt, env: &mut Env<'a>) -> Result<(), RuntimeError> {
+        let mut tasks = vec![Task::Execute(stmt)];
+        
+        while let Some(task) = tasks.pop() {
+            let stmt = match task {
+                Task::Execute(stmt) => stmt,
+                Task::Return => {
+                    env.call_stack.pop();
+                    continue;
+                }
+            };
+            
+            if let Some(span) = stmt.span() {
+                env.count_step(span.start)?;
             }
-            AstNode::Term {ref factors, ref ops} => {
-                // println!("factors = {:?}", factors);
-                let first_op = [BiOp::Mul];
-                let v = factors.iter().map(|f| Self::visit_impl(f, call_stack)).zip(first_op.iter().chain(ops));
-                
-                let ret = v.fold(1, |acc, (val, op)| {
-                    match *op {
-                        BiOp::Mul => {
-                            acc * val.unwrap()
-                        },
-                        BiOp::Div => {
-                            acc / val.unwrap()
-                        }
+            match *stmt {
+                Stmt::Empty => {}
+                Stmt::Error(ref e) => return Err(RuntimeError::SyntaxError(e.clone())),
+                Stmt::BeginEnd(_, ref statements) => {
+                    tasks.extend(statements.iter().rev().map(Task::Execute));
+                }
+                Stmt::IfThen {ref condition, ref statement, ..} => {
+                    if Self::evaluate_codition(condition, env)? {
+                        tasks.push(Task::Execute(statement));
                     }
-                });
-                
-                Some(ret)
-            }
-            AstNode::Expression {ref terms, ref signs} => {
-                let (first_val, r_terms) = if terms.len() == signs.len() {
-                    (0, terms.iter().skip(0))
-                } else {
-                    (Self::visit_impl(&terms[0], call_stack).unwrap(), terms.iter().skip(1))
-                };
-                let v = r_terms.map(|f| Self::visit_impl(f, call_stack)).zip(signs);
-                
-                let ret = v.fold(first_val, |acc, (val, op)| {
-                    match *op {
-                        Sign::Plus => acc + val.unwrap(),
-                        Sign::Minus => acc - val.unwrap()
+                }
+                // The loop comes back to itself after each pass of the body.
+                Stmt::WhileDo {span, ref condition, ref statement} => {
+                    if Self::evaluate_codition(condition, env)? {
+                        env.check_deadline(span.start)?;
+                        tasks.push(Task::Execute(stmt));
+                        tasks.push(Task::Execute(statement));
                     }
-                });
-                // println!("ex = {:?}", ret);
-                Some(ret)
-            }
-            AstNode::Odd(_) => {
-                None
-            }
-            AstNode::ComposedExpression {..} => {
-                None
-            }
-            AstNode::BeginEnd(ref statements) => {
-                for s in statements {
-                    Self::visit_impl(s, call_stack);
                 }
-                None
-            }
-            AstNode::IfThen {ref condition, ref statement} => {
-                if Self::evaluate_codition(condition, call_stack) {
-                    Self::visit_impl(statement, call_stack);
+                Stmt::Assign {ref target, ref value, ..} => {
+                    let val = Self::evaluate(value, env)?;
+                    
+                    *Self::get_var_entry(&mut env.call_stack, target)? = val;
                 }
-                None
-            }
-            AstNode::WhileDo {ref condition, ref statement} => {
-                while Self::evaluate_codition(condition, call_stack) {
-                    Self::visit_impl(statement, call_stack);
+                Stmt::Call(_, ref ident) => {
+                    env.check_deadline(ident.span.start)?;
+                    
+                    // Looked up like variables, so that procedures can call
+                    // themselves and their siblings. Native procedures come
+                    // last.
+                    let found = Self::find_frame(&env.call_stack, |frame| frame.procedures.contains_key(&ident.name));
+                    let (declared_in, p) = match found {
+                        Some(i) => (i, env.call_stack[i].procedures[&ident.name]),
+                        None => {
+                            let native = env.natives.procedures.get(&ident.name)
+                                .ok_or_else(|| RuntimeError::UndefinedProcedure(ident.clone()))?;
+                            native(&mut *env.io).map_err(|e| RuntimeError::Native(ident.clone(), e))?;
+                            continue;
+                        }
+                    };
+                    
+                    // The main program's frame is not a call.
+                    if env.limits.max_call_depth.is_some_and(|max| env.call_stack.len() > max) {
+                        return Err(RuntimeError::LimitExceeded(Limit::CallDepth, ident.span.start));
+                    }
+                    let mut frame = Frame::new(Some(declared_in));
+                    Self::declare(p, &mut frame);
+                    env.call_stack.push(frame);
+                    tasks.push(Task::Return);
+                    tasks.push(Task::Execute(&p.statement));
                 }
-                None
-            }
-            AstNode::Assignment {ref ident, ref expression} => {
-                let ident = Self::get_ident(ident);
-                // println!("assign called, ident = {}", ident);
-                // println!("ex = {:?}", expression);
-                
-                let ex_ret = Self::visit_impl(expression, call_stack);
-                
-                let e = Self::get_var_entry(call_stack, ident);
-                
-                *e = ex_ret.unwrap();
-                
-                None
-            }
-            AstNode::Call(ref ident) => {
-                let ident = Self::get_ident(ident);
-                
-                let p = {
-                    let curr_scope = call_stack.last_mut().unwrap();
-                    *curr_scope.1.get(&ident).unwrap()
-                };
-                
-                let v_s: HashMap<String, i32> = HashMap::new();
-                let p_map: HashMap<String, &AstNode<'a>> = HashMap::new();
-                call_stack.push((v_s, p_map));
-                
-                Self::visit_impl(p, call_stack);
-                call_stack.pop();
-                
-                None
-            }
-            AstNode::QuestionMark(ref ident) => {
-                let mut input_text = String::new();
-                io::stdin()
-                    .read_line(&mut input_text)
-                    .expect("failed to read from stdin");
-
-                let trimmed = input_text.trim();
-                match trimmed.parse::<i32>() {
-                    Ok(i) => {
-                        let ident = Self::get_ident(ident);
-                        let e = Self::get_var_entry(call_stack, ident);
-                
-                        *e = i;
+                Stmt::Read(_, ref ident) => {
+                    let input_text = env.io.read_line()
+                        .map_err(|e| RuntimeError::Io(ident.span.start, e.kind(), e.to_string()))?
+                        .unwrap_or_default();
+                    
+                    let trimmed = input_text.trim();
+                    let (min, max) = env.mode.bounds();
+                    match trimmed.parse::<i64>() {
+                        Ok(i) if i as i128 >= min && i as i128 <= max => {
+                            *Self::get_var_entry(&mut env.call_stack, ident)? = i;
+                        }
+                        _ => return Err(RuntimeError::InvalidInput(ident.span.start)),
+                    };
+                }
+                Stmt::Write {span, ref items, newline} => {
+                    let mut line = String::new();
+                    for item in items {
+                        match *item {
+                            WriteItem::Str(_, ref s) => line.push_str(s),
+                            WriteItem::Expr(ref ex) => {
+                                line.push_str(&Self::evaluate(ex, env)?.to_string());
+                            }
+                        }
                     }
-                    Err(..) => panic!("wrong input"),
-                };
-                // TODO
-                None
+                    
+                    if newline {
+                        line.push('\n');
+                    }
+                    env.io.write(&line).map_err(|e| RuntimeError::Io(span.start, e.kind(), e.to_string()))?;
+                }
             }
-            AstNode::ExclaimationMark(ref expression) => {
-                let ex_ret = Self::visit_impl(expression, call_stack).unwrap();
-                println!("{}", ex_ret);
-                None
+        }
+        Ok(())
+    }
+    
+    fn evaluate(expr: &Expr, env: &mut Env) -> Result<i64, RuntimeError> {
+        match *expr {
+            Expr::Number(_, num, _) => Ok(num),
+            Expr::Ident(ref ident) => {
+                Ok(*Self::get_var_entry(&mut env.call_stack, ident)?)
             }
-            AstNode::Const {ref ident, ref value} => {
-                // println!("const");
-                let curr_scope = call_stack.last_mut().unwrap();
-                
-                let ident = Self::get_ident(ident);
-                let val = Self::get_number(value);
-                
-                curr_scope.0.insert(ident, val);
-                None
+            Expr::Negate(pos, ref ex) => {
+                let val = Self::evaluate(ex, env)?;
+                env.mode.sub(0, val, pos)
             }
-            AstNode::Procedure {ref ident, ref block} => {
-                let ident = Self::get_ident(ident);
-                // println!("inserting pro: {}", ident);
-                let curr_scope = call_stack.last_mut().unwrap();
-                
-                curr_scope.1.insert(ident, block);
-                None
+            Expr::Binary {ref lhs, op, pos, ref rhs} => {
+                let lhs = Self::evaluate(lhs, env)?;
+                let rhs = Self::evaluate(rhs, env)?;
+                match op {
+                    BinOp::Add => env.mode.add(lhs, rhs, pos),
+                    BinOp::Sub => env.mode.sub(lhs, rhs, pos),
+                    BinOp::Mul => env.mode.mul(lhs, rhs, pos),
+                    BinOp::Div => env.mode.div(lhs, rhs, pos),
+                    BinOp::Mod => env.mode.rem(lhs, rhs, pos),
+                }
             }
-            AstNode::Block {ref const_decl, ref var_decl, ref procedures, ref statement} => {
-                for c_decl in const_decl {
-                    Self::visit_impl(c_decl, call_stack);
+            Expr::Call {ref name, ref args, ..} => {
+                let &(arity, ref function) = env.natives.functions.get(&name.name)
+                    .ok_or_else(|| RuntimeError::UndefinedFunction(name.clone()))?;
+                if args.len() != arity {
+                    return Err(RuntimeError::WrongArgumentCount { function: name.clone(), expected: arity, found: args.len() });
                 }
-                for v_decl in var_decl {
-                    let curr_scope = call_stack.last_mut().unwrap();
                 
-                    let ident = Self::get_ident(v_decl);
-                    let val = 0;
-                    
-                    curr_scope.0.insert(ident, val);
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(Self::evaluate(arg, env)?);
                 }
-                for p in procedures {
-                    Self::visit_impl(p, call_stack);
-                }
-                Self::visit_impl(statement, call_stack);
-                // println!("block");
-                None
+                let value = function(&values).map_err(|e| RuntimeError::Native(name.clone(), e))?;
+                env.mode.fit(value as i128, name.span.start)
             }
         }
     }
     
-    fn evaluate_codition<'b>(node: &'b AstNode<'a>, call_stack: &mut Vec<(HashMap<String, i32>, HashMap<String, &'b AstNode<'a>>)>) -> bool {
-        match *node {
-            AstNode::Odd(ref ex) => {
-                let r = Self::visit_impl(ex, call_stack).unwrap();
+    fn evaluate_codition(cond: &Cond, env: &mut Env) -> Result<bool, RuntimeError> {
+        match *cond {
+            Cond::Odd(_, ref ex) => {
+                let r = Self::evaluate(ex, env)?;
                 
-                if r % 2 == 0 { false } else { true }
+                Ok(r % 2 != 0)
             }
-            AstNode::ComposedExpression {ref ex1, ref op, ref ex2} => {
-                let ex_ret1 = Self::visit_impl(ex1, call_stack).unwrap();
-                let ex_ret2 = Self::visit_impl(ex2, call_stack).unwrap();
+            Cond::Compare {ref lhs, op, ref rhs} => {
+                let ex_ret1 = Self::evaluate(lhs, env)?;
+                let ex_ret2 = Self::evaluate(rhs, env)?;
                 
-                match *op {
+                Ok(match op {
                     ExOp::Equal => ex_ret1 == ex_ret2,
                     ExOp::NumberSign => ex_ret1 != ex_ret2,
                     ExOp::LessThan => ex_ret1 < ex_ret2,
                     ExOp::LessThanOrEqual => ex_ret1 <= ex_ret2,
                     ExOp::GreaterThan => ex_ret1 > ex_ret2,
                     ExOp::GreaterThanOrEqual => ex_ret1 >= ex_ret2,
-                }
+                })
             },
-            _ => panic!("invalid condition")
-        }
-    }
-    
-    fn get_ident(node: &AstNode<'a>) -> String {
-        if let &AstNode::Ident(s) = node {
-            s.to_owned()
-        } else {
-            panic!("asdf");
+            Cond::Not(_, ref cond) => {
+                Ok(!Self::evaluate_codition(cond, env)?)
+            }
+            Cond::Logic {ref lhs, op, ref rhs} => {
+                Ok(match op {
+                    LogicOp::And => Self::evaluate_codition(lhs, env)? && Self::evaluate_codition(rhs, env)?,
+                    LogicOp::Or => Self::evaluate_codition(lhs, env)? || Self::evaluate_codition(rhs, env)?,
+                })
+            }
         }
     }
     
-    fn get_number(node: &AstNode<'a>) -> i32 {
-        if let &AstNode::Number(n) = node {
-            n
-        } else {
-            panic!("asdf");
+    // Names resolve lexically: in the current frame, then in the frames of
+    // the blocks around it, innermost first.
+    fn find_frame<F: Fn(&Frame) -> bool>(call_stack: &[Frame<'_>], has_name: F) -> Option<usize> {
+        let mut frame = call_stack.len().checked_sub(1);
+        while let Some(i) = frame {
+            if has_name(&call_stack[i]) {
+                return Some(i);
+            }
+            frame = call_stack[i].parent;
         }
+        None
     }
     
-    fn get_var_entry<'b>(call_stack: &'b mut Vec<(HashMap<String, i32>, HashMap<String, &AstNode<'a>>)>, var_name: String) -> &'b mut i32 {
-        
-        for vp in call_stack.iter_mut().rev() {
-            //let (v, _):() = vp;
-            if let Some(x) = vp.0.get_mut(&var_name) {
-                return x;
-            }
+    fn get_var_entry<'b>(call_stack: &'b mut [Frame<'_>], var: &Ident) -> Result<&'b mut i64, RuntimeError> {
+        match Self::find_frame(call_stack, |frame| frame.vars.contains_key(&var.name)) {
+            Some(i) => Ok(call_stack[i].vars.get_mut(&var.name).unwrap()),
+            None => Err(RuntimeError::UndefinedVariable(var.clone()))
         }
-        panic!("variable not found");
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_short_circuit_conditions() {
+    use lexer::*;
+    use parser::parse_program;
+
+    // Without short-circuiting both divisions by zero would panic.
+    let tokens = r_lexer("
+VAR x, y;
+BEGIN
+   x := 5;
+   y := 0;
+   IF y # 0 AND x / y > 1 THEN x := 0;
+   IF y = 0 OR x / y > 1 THEN x := x + 1;
+   IF NOT (x = 6) THEN x := 0;
+   y := 1 / x
+END.").unwrap();
+    let ast = parse_program(&tokens).unwrap();
+
+    Interpreter::new(ast).run().unwrap();
+}
+
+#[test]
+fn test_mod_and_negation() {
+    use lexer::*;
+    use parser::parse_program;
+
+    // Every check divides by zero when it does not hold.
+    let tokens = r_lexer("
+VAR x;
+BEGIN
+   x := 7;
+   IF -x MOD 3 # -1 THEN x := 1 / 0;
+   IF x MOD -3 # 1 THEN x := 1 / 0;
+   IF x % 3 * -2 # -2 THEN x := 1 / 0;
+   IF (-x / 2) * 2 + -x MOD 2 # -x THEN x := 1 / 0
+END.").unwrap();
+    let ast = parse_program(&tokens).unwrap();
+
+    Interpreter::new(ast).run().unwrap();
+}
+
+#[test]
+fn test_overflow_modes() {
+    use lexer::*;
+    use parser::parse_program;
+
+    let tokens = r_lexer("
+VAR x;
+BEGIN
+   x := 2147483647;
+   x := x + 1;
+   IF x < 0 THEN x := 1 / 0
+END.").unwrap();
+    let ast = parse_program(&tokens).unwrap();
+
+    let checked = Interpreter::new(ast.clone()).run();
+    assert_eq!(checked, Err(RuntimeError::Overflow(Pos { line: 5, col: 11 })));
+
+    let run_with = |width, overflow| {
+        let mut interpreter = Interpreter::new(ast.clone());
+        interpreter.set_mode(RunMode { width, overflow });
+        interpreter.run()
+    };
+    assert_eq!(run_with(IntWidth::I32, Overflow::Wrapping),
+               Err(RuntimeError::DivisionByZero(Pos { line: 6, col: 25 })));
+    assert_eq!(run_with(IntWidth::I32, Overflow::Saturating), Ok(()));
+    assert_eq!(run_with(IntWidth::I64, Overflow::Checked), Ok(()));
+}
+
+#[test]
+fn test_errors_are_reported_not_panicked() {
+    use lexer::*;
+    use parser::parse_program;
+
+    let run = |source: &str, input: &str| {
+        let program = match parse_program(&r_lexer(source).unwrap()) {
+            Ok(program) => program,
+            Err(failure) => *failure.partial,
+        };
+        Interpreter::new(program).run_with(&mut MemoryIo::new(input)).unwrap_err().to_string()
+    };
+
+    assert_eq!(run("BEGIN x := 1 END.", ""), "1:7: undefined variable `x`");
+    assert_eq!(run("VAR x; x := y.", ""), "1:13: undefined variable `y`");
+    assert_eq!(run("CALL p.", ""), "1:6: undefined procedure `p`");
+    assert_eq!(run("VAR x; BEGIN ? x; ? x END.", "1\nten\n"), "1:21: input is not a number");
+    assert_eq!(run("VAR x; ? x.", ""), "1:10: input is not a number");
+    assert_eq!(run("VAR x; BEGIN x := 1; x := END.", ""), "cannot run a program with syntax errors: 1:27: expected expression, found `END`");
+}
+
+#[test]
+fn test_names_resolve_lexically() {
+    use lexer::*;
+    use parser::parse_program;
+
+    // `q` sees the global `x`, not the one of `p`, which calls it.
+    let tokens = r_lexer("
+VAR x;
+PROCEDURE q; x := 1;
+PROCEDURE p;
+   VAR x;
+   CALL q;
+BEGIN CALL p; ! x END.").unwrap();
+    let mut io = MemoryIo::default();
+    Interpreter::new(parse_program(&tokens).unwrap()).run_with(&mut io).unwrap();
+    assert_eq!(io.output, "1\n");
+}
+
+#[test]
+fn test_limits() {
+    use lexer::*;
+    use parser::parse_program;
+
+    let run = |source: &str, limits| {
+        let mut interpreter = Interpreter::new(parse_program(&r_lexer(source).unwrap()).unwrap());
+        interpreter.set_limits(limits);
+        interpreter.run_with(&mut MemoryIo::default())
+    };
+    let endless = "VAR x; BEGIN x := 0; WHILE 1 = 1 DO x := x + 1 END.";
+    let recursive = "PROCEDURE p; CALL p; CALL p.";
+
+    let steps = Limits { max_steps: Some(100), ..Limits::default() };
+    assert_eq!(run(endless, steps), Err(RuntimeError::LimitExceeded(Limit::Steps, Pos { line: 1, col: 22 })));
+    assert_eq!(run("VAR x; WHILE x < 10 DO x := x + 1.", steps), Ok(()));
+    let three = Limits { max_steps: Some(3), ..Limits::default() };
+    assert_eq!(run("VAR x; BEGIN x := 1; x := 2; x := 3 END.", three),
+               Err(RuntimeError::LimitExceeded(Limit::Steps, Pos { line: 1, col: 30 })));
+
+    let time = Limits { timeout: Some(Duration::from_millis(10)), ..Limits::default() };
+    assert_eq!(run(endless, time), Err(RuntimeError::LimitExceeded(Limit::Time, Pos { line: 1, col: 22 })));
+
+    let depth = Limits { max_call_depth: Some(20), ..Limits::default() };
+    assert_eq!(run(recursive, depth), Err(RuntimeError::LimitExceeded(Limit::CallDepth, Pos { line: 1, col: 19 })));
+    assert_eq!(run(recursive, depth).unwrap_err().to_string(), "1:19: stopped: procedure calls nested too deeply");
+}
+
+#[test]
+fn test_default_limits_fit_the_stack() {
+    use lexer::*;
+    use parser::parse_program;
+
+    // Test threads have 2 MB of stack, less than a main thread.
+    let run = |source: &str| {
+        let program = parse_program(&r_lexer(source).unwrap()).unwrap();
+        Interpreter::new(program).run_with(&mut MemoryIo::default())
+    };
+
+    assert_eq!(run("PROCEDURE p; CALL p; CALL p."),
+               Err(RuntimeError::LimitExceeded(Limit::CallDepth, Pos { line: 1, col: 19 })));
+
+    // As deep as the default allows, with deeply nested statements in
+    // every call.
+    let nested = format!("VAR n; PROCEDURE p; {}IF n > 0 THEN BEGIN n := n - 1; CALL p END{}; BEGIN n := 999; CALL p END.",
+                         "BEGIN ".repeat(90), " END".repeat(90));
+    assert_eq!(run(&nested), Ok(()));
+}
+
+#[test]
+fn test_ast_outlives_source() {
+    use lexer::*;
+    use parser::parse_program;
+    use std::thread;
+
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let source = String::from("VAR x; BEGIN x := 6; x := x * 7 END.");
+    let ast = {
+        let tokens = r_lexer(&source).unwrap();
+        parse_program(&tokens).unwrap()
+    };
+    drop(source);
+    assert_send_sync(&ast);
+
+    let result = thread::spawn(move || Interpreter::new(ast).run()).join().unwrap();
+    assert_eq!(result, Ok(()));
+}
@@ -1,115 +1,717 @@
-use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::BufRead;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use unicode_xid::UnicodeXID;
 
-use chomp::*;
-use std::collections::HashSet;
+/// 1-based line and column (in characters) of a token in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Pos {
+    fn advance(&mut self, text: &str) {
+        for c in text.chars() {
+            if c == '\n' {
+                self.line += 1;
+                self.col = 1;
+            } else {
+                self.col += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntWidth {
+    #[default]
+    I32,
+    I64
+}
+
+impl IntWidth {
+    pub fn max(&self) -> i64 {
+        match *self {
+            IntWidth::I32 => i32::MAX as i64,
+            IntWidth::I64 => i64::MAX,
+        }
+    }
+    
+    pub fn bits(&self) -> u32 {
+        match *self {
+            IntWidth::I32 => 32,
+            IntWidth::I64 => 64,
+        }
+    }
+}
+
+/// How a number literal was written, so it can be printed back the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Radix {
+    Decimal,
+    Hex,
+    Binary
+}
+
+/// Prints a literal in its radix. Digit separators are not kept, and a
+/// negative value is printed as a sign and its magnitude, e.g. `-0x5`.
+pub fn format_number(value: i64, radix: Radix) -> String {
+    let sign = if value < 0 { "-" } else { "" };
+    let magnitude = value.unsigned_abs();
+    match radix {
+        Radix::Decimal => value.to_string(),
+        Radix::Hex => format!("{}0x{:X}", sign, magnitude),
+        Radix::Binary => format!("{}0b{:b}", sign, magnitude),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'a> {
+    pub kind: TokenKind<&'a str>,
+    pub pos: Pos,
+    // The source text as written, before any normalisation.
+    pub text: &'a str,
+}
+
+impl<'a> Token<'a> {
+    pub fn into_owned(self) -> OwnedToken {
+        OwnedToken {
+            kind: self.kind.map(str::to_owned),
+            pos: self.pos,
+            text: self.text.to_owned()
+        }
+    }
+}
+
+/// A token that does not borrow the source, as produced by `ReadLexer`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OwnedToken {
+    pub kind: TokenKind<String>,
+    pub pos: Pos,
+    pub text: String,
+}
+
+/// Identifiers start with `_` or a Unicode XID_Start character and continue
+/// with XID_Continue characters (letters, digits, `_` and combining marks).
+/// Two identifiers are the same if their NFC forms are equal. Identifiers
+/// are reported with the spelling of their first occurrence, so later
+/// passes can compare them as written. `max_ident_len`, counted in
+/// characters of the NFC form, makes longer identifiers an error.
+///
+/// Number literals are decimal, `0x` hexadecimal or `0b` binary, with
+/// single `_` separators allowed between digits. A literal that does not fit
+/// in `int_width` is an error.
+///
+/// Keywords are always reported in their canonical uppercase spelling.
+/// With `case_insensitive_idents`, identifiers that only differ in case
+/// are the same too.
+///
+/// Comments are skipped unless `keep_comments` is set, in which case they
+/// are returned as `Comment` tokens. The parser does not accept those, so
+/// only tools that work on the token stream should ask for them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LexerOptions {
+    pub case_insensitive_keywords: bool,
+    pub case_insensitive_idents: bool,
+    pub keep_comments: bool,
+    pub max_ident_len: Option<usize>,
+    pub int_width: IntWidth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind<S> {
+    Number(i64, Radix),
+    Ident(S),
+    Keyword(S),
+    Separator(S),
+    // Contents between the quotes, escapes left as written.
+    Str(S),
+    // The whole comment, delimiters included.
+    Comment(S),
+}
+
+impl<S> TokenKind<S> {
+    pub fn map<T, F: FnOnce(S) -> T>(self, f: F) -> TokenKind<T> {
+        match self {
+            TokenKind::Number(n, radix) => TokenKind::Number(n, radix),
+            TokenKind::Ident(s) => TokenKind::Ident(f(s)),
+            TokenKind::Keyword(s) => TokenKind::Keyword(f(s)),
+            TokenKind::Separator(s) => TokenKind::Separator(f(s)),
+            TokenKind::Str(s) => TokenKind::Str(f(s)),
+            TokenKind::Comment(s) => TokenKind::Comment(f(s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexErrorKind {
+    UnexpectedChar(char),
+    InvalidNumber,
+    NumberOutOfRange(IntWidth),
+    UnterminatedString,
+    InvalidEscape,
+    UnterminatedComment,
+    IdentTooLong(usize),
+    // Reading from a `ReadLexer` source failed.
+    Io(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub pos: Pos,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            LexErrorKind::UnexpectedChar(c) => write!(f, "{}: unexpected character `{}`", self.pos, c),
+            LexErrorKind::InvalidNumber => write!(f, "{}: malformed number literal", self.pos),
+            LexErrorKind::NumberOutOfRange(width) => {
+                write!(f, "{}: number literal does not fit in {} bits", self.pos, width.bits())
+            }
+            LexErrorKind::UnterminatedString => write!(f, "{}: unterminated string literal", self.pos),
+            LexErrorKind::InvalidEscape => write!(f, "{}: invalid escape in string literal", self.pos),
+            LexErrorKind::UnterminatedComment => write!(f, "{}: unterminated comment", self.pos),
+            LexErrorKind::IdentTooLong(max) => write!(f, "{}: identifier longer than {} characters", self.pos, max),
+            LexErrorKind::Io(ref e) => write!(f, "{}: {}", self.pos, e),
+        }
+    }
+}
+
+/// Resolves the escapes (`\n`, `\t`, `\\`, `\"`) in the contents of a
+/// string literal token.
+pub fn unescape_string(raw: &str) -> String {
+    let mut ret = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('t') => ret.push('\t'),
+            Some(other) => ret.push(other),
+            None => {}
+        }
+    }
+    ret
+}
+
+/// Inverse of `unescape_string`: the literal contents that read back as `s`.
+pub fn escape_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    
+    for c in s.chars() {
+        match c {
+            '\n' => ret.push_str("\\n"),
+            '\t' => ret.push_str("\\t"),
+            '\\' => ret.push_str("\\\\"),
+            '"' => ret.push_str("\\\""),
+            _ => ret.push(c)
+        }
+    }
+    ret
+}
+
+const KEYWORDS: [&str; 15] = [
+    "BEGIN", "END", "PROCEDURE", "WHILE", "DO", "IF", "THEN", "CALL",
+    "ODD", "VAR", "CONST", "AND", "OR", "NOT", "MOD"
+];
+
+// Canonical spelling of `value` if it is a keyword.
+fn keyword(value: &str, case_insensitive: bool) -> Option<&'static str> {
+    if case_insensitive {
+        KEYWORDS.iter().find(|kw| kw.eq_ignore_ascii_case(value)).cloned()
+    } else {
+        KEYWORDS.iter().find(|kw| **kw == value).cloned()
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    if c.is_ascii() {
+        c.is_ascii_alphabetic() || c == '_'
+    } else {
+        UnicodeXID::is_xid_start(c)
+    }
+}
+
+fn is_ident_continue(c: char) -> bool {
+    if c.is_ascii() {
+        c.is_ascii_alphanumeric() || c == '_'
+    } else {
+        UnicodeXID::is_xid_continue(c)
+    }
+}
+
+// Length of the identifier at the start of the input, and whether it is
+// all ASCII.
+fn ident_len(input: &str) -> (usize, bool) {
+    // Most identifiers are ASCII; only fall back to chars past the first
+    // non-ASCII byte.
+    let ascii_len = input.bytes().take_while(|b| b.is_ascii_alphanumeric() || *b == b'_').count();
+    let rest = &input[ascii_len..];
+    
+    if rest.as_bytes().first().is_none_or(u8::is_ascii) {
+        return (ascii_len, true);
+    }
+    (ascii_len + rest.find(|c| !is_ident_continue(c)).unwrap_or(rest.len()), false)
+}
 
+// Hashes and compares identifiers by their NFC form, ignoring case if
+// asked to, without allocating.
 #[derive(Debug, Clone, Copy)]
-pub enum Token<'a> {
-    Number(i32),
-    Ident(&'a str),
-    Keyword(&'a str),
-    Separator(&'a str),
+struct IdentKey<'a> {
+    text: &'a str,
+    fold_case: bool,
 }
 
-pub fn r_lexer(input: &str) -> Result<Vec<Token>, String> {
-    fn r_number(input: &str) -> Option<(Token, usize, usize)> {
-        let re = Regex::new(r"^\d+").unwrap();
+impl<'a> PartialEq for IdentKey<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        if self.fold_case {
+            self.text.nfc().flat_map(char::to_lowercase).eq(other.text.nfc().flat_map(char::to_lowercase))
+        } else {
+            self.text.nfc().eq(other.text.nfc())
+        }
+    }
+}
+
+impl<'a> Eq for IdentKey<'a> {}
+
+impl<'a> Hash for IdentKey<'a> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        if self.fold_case {
+            self.text.nfc().flat_map(char::to_lowercase).for_each(|c| c.hash(state));
+        } else {
+            self.text.nfc().for_each(|c| c.hash(state));
+        }
+    }
+}
+
+// Owned counterpart of `IdentKey`, for `ReadLexer`.
+fn ident_key_string(text: &str, fold_case: bool) -> String {
+    if fold_case {
+        text.nfc().flat_map(char::to_lowercase).collect()
+    } else {
+        text.nfc().collect()
+    }
+}
+
+// Length of the `{ }`, `(* *)` or `//` comment at the start of the input.
+// Block comments nest with their own kind of delimiter.
+fn comment_len(input: &str) -> Result<Option<usize>, LexErrorKind> {
+    if input.starts_with("//") {
+        return Ok(Some(input.find('\n').unwrap_or(input.len())));
+    }
+    
+    let mut comment = match BlockComment::start(input) {
+        Some(comment) => comment,
+        None => return Ok(None)
+    };
+    if comment.scan(input) {
+        Ok(Some(comment.idx))
+    } else {
+        Err(LexErrorKind::UnterminatedComment)
+    }
+}
+
+// How far a block comment at the start of some input has been scanned, so
+// that a scan can resume once more input arrives.
+#[derive(Debug, Clone, Copy)]
+struct BlockComment {
+    open: &'static [u8],
+    close: &'static [u8],
+    depth: usize,
+    // The next byte to look at.
+    idx: usize,
+}
+
+impl BlockComment {
+    fn start(input: &str) -> Option<Self> {
+        let (open, close): (&'static [u8], &'static [u8]) = if input.starts_with('{') {
+            (b"{", b"}")
+        } else if input.starts_with("(*") {
+            (b"(*", b"*)")
+        } else {
+            return None;
+        };
+        Some(BlockComment { open, close, depth: 0, idx: 0 })
+    }
+    
+    // Scans on through `input`, true once the comment is closed, with `idx`
+    // just past it. Input may only grow by whole lines between calls, so no
+    // delimiter is ever cut in two.
+    //
+    // The delimiters are ASCII, so stepping over bytes never splits a
+    // delimiter out of a multi-byte character.
+    fn scan(&mut self, input: &str) -> bool {
+        let bytes = input.as_bytes();
+        while self.idx < bytes.len() {
+            let rest = &bytes[self.idx..];
+            if rest.starts_with(self.open) {
+                self.depth += 1;
+                self.idx += self.open.len();
+            } else if rest.starts_with(self.close) {
+                self.depth -= 1;
+                self.idx += self.close.len();
+                if self.depth == 0 {
+                    return true;
+                }
+            } else {
+                self.idx += 1;
+            }
+        }
+        false
+    }
+}
+
+// Length of the string literal at the start of the input, quotes included.
+fn string_len(input: &str) -> Result<usize, LexErrorKind> {
+    let bytes = input.as_bytes();
+    let mut idx = 1;
+    
+    while idx < bytes.len() {
+        match bytes[idx] {
+            b'"' => return Ok(idx + 1),
+            b'\n' => break,
+            b'\\' => match bytes.get(idx + 1) {
+                Some(&b'n') | Some(&b't') | Some(&b'"') | Some(&b'\\') => idx += 2,
+                _ => return Err(LexErrorKind::InvalidEscape)
+            },
+            _ => idx += 1
+        }
+    }
+    Err(LexErrorKind::UnterminatedString)
+}
+
+// Length and value of the number literal at the start of the input.
+fn number(input: &str, width: IntWidth) -> Result<(usize, i64, Radix), LexErrorKind> {
+    let bytes = input.as_bytes();
+    let (radix, base, prefix_len) = match bytes.get(..2) {
+        Some(b"0x") | Some(b"0X") => (Radix::Hex, 16, 2),
+        Some(b"0b") | Some(b"0B") => (Radix::Binary, 2, 2),
+        _ => (Radix::Decimal, 10, 0)
+    };
+    
+    let digits_len = bytes[prefix_len..].iter()
+        .take_while(|b| **b == b'_' || (**b as char).is_digit(base))
+        .count();
+    let digits = &input[prefix_len..prefix_len + digits_len];
+    
+    if digits.is_empty() || digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(LexErrorKind::InvalidNumber);
+    }
+    
+    let mut value: i64 = 0;
+    for c in digits.chars().filter(|c| *c != '_') {
+        value = value.checked_mul(base as i64)
+            .and_then(|v| v.checked_add(c.to_digit(base).unwrap() as i64))
+            .filter(|v| *v <= width.max())
+            .ok_or(LexErrorKind::NumberOutOfRange(width))?;
+    }
+    Ok((prefix_len + digits_len, value, radix))
+}
+
+fn separator_len(input: &str) -> Option<usize> {
+    let bytes = input.as_bytes();
+    
+    match bytes[0] {
+        b':' | b'>' | b'<' if bytes.get(1) == Some(&b'=') => Some(2),
+        b'>' | b'<' | b',' | b'.' | b';' | b'=' | b'+' | b'-' | b'*' | b'/' | b'%'
+        | b'#' | b'!' | b'?' | b'(' | b')' => Some(1),
+        _ => None
+    }
+}
+
+/// Lexes a string in a single pass, yielding tokens that borrow from it.
+/// Nothing is allocated per token. Iteration stops after the first error.
+pub struct Lexer<'a> {
+    input: &'a str,
+    idx: usize,
+    pos: Pos,
+    options: LexerOptions,
+    // Identifier -> spelling of its first occurrence, when case-insensitive.
+    ident_spellings: HashMap<IdentKey<'a>, &'a str>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str, options: LexerOptions) -> Self {
+        Self::starting_at(input, options, Pos { line: 1, col: 1 })
+    }
+    
+    fn starting_at(input: &'a str, options: LexerOptions, pos: Pos) -> Self {
+        Lexer {
+            input,
+            idx: 0,
+            pos,
+            options,
+            ident_spellings: HashMap::new()
+        }
+    }
+    
+    fn fail(&mut self, kind: LexErrorKind, pos: Pos) -> Option<Result<Token<'a>, LexError>> {
+        self.idx = self.input.len();
+        Some(Err(LexError { kind, pos }))
+    }
+    
+    fn bump(&mut self, len: usize) -> &'a str {
+        let text = &self.input[self.idx..self.idx + len];
+        self.pos.advance(text);
+        self.idx += len;
+        text
+    }
+    
+    fn ident_or_keyword(&mut self, value: &'a str, ascii: bool) -> TokenKind<&'a str> {
+        let fold_case = self.options.case_insensitive_idents;
         
-        if let Some((start, end)) = re.find(input) {
-            let num = input[start..end].parse::<i32>().unwrap();
-            return Some((Token::Number(num), start, end));
-        }
-        None
-    }
-    
-    fn r_ident_keyword(input: &str) -> Option<(Token, usize, usize)> {
-        let keywords = {
-            let mut kw = HashSet::new();
-            kw.insert("BEGIN");
-            kw.insert("END");
-            kw.insert("PROCEDURE");
-            kw.insert("WHILE");
-            kw.insert("DO");
-            kw.insert("IF");
-            kw.insert("THEN");
-            kw.insert("CALL");
-            kw.insert("ODD");
-            kw.insert("VAR");
-            kw.insert("CONST");
+        if let Some(kw) = keyword(value, self.options.case_insensitive_keywords) {
+            TokenKind::Keyword(kw)
+        } else if fold_case || !ascii {
+            // ASCII is already in NFC, so only these can have other spellings.
+            TokenKind::Ident(self.first_spelling(value))
+        } else {
+            TokenKind::Ident(value)
+        }
+    }
+    
+    // Kept out of line so the common ASCII path stays small.
+    #[inline(never)]
+    fn first_spelling(&mut self, value: &'a str) -> &'a str {
+        let key = IdentKey { text: value, fold_case: self.options.case_insensitive_idents };
+        self.ident_spellings.entry(key).or_insert(value)
+    }
+    
+    fn next_token(&mut self) -> Option<Result<Token<'a>, LexError>> {
+        loop {
+            let rest = &self.input[self.idx..];
+            let c = rest.chars().next()?;
+            let pos = self.pos;
             
-            kw
-        }; 
-        
-        let re = Regex::new(r"^[:alpha:]([:alpha:]|\d)*").unwrap();
-        
-        if let Some((start, end)) = re.find(input) {
-            let value = &input[start..end];
+            if c.is_whitespace() {
+                let len = rest.find(|c: char| !c.is_whitespace()).unwrap_or(rest.len());
+                self.bump(len);
+                continue;
+            }
             
-            if keywords.contains(value) {
-                return Some((Token::Keyword(value), start, end))
+            match comment_len(rest) {
+                Ok(Some(len)) => {
+                    let text = self.bump(len);
+                    if self.options.keep_comments {
+                        return Some(Ok(Token { kind: TokenKind::Comment(text), pos, text }));
+                    }
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => return self.fail(e, pos)
             }
             
-            return Some((Token::Ident(value), start, end));
+            let (kind, len) = if c.is_ascii_digit() {
+                match number(rest, self.options.int_width) {
+                    Ok((len, num, radix)) => (TokenKind::Number(num, radix), len),
+                    Err(e) => return self.fail(e, pos)
+                }
+            } else if is_ident_start(c) {
+                let (len, ascii) = ident_len(rest);
+                let value = &rest[..len];
+                if let Some(max) = self.options.max_ident_len {
+                    if value.nfc().count() > max {
+                        return self.fail(LexErrorKind::IdentTooLong(max), pos);
+                    }
+                }
+                (self.ident_or_keyword(value, ascii), len)
+            } else if c == '"' {
+                match string_len(rest) {
+                    Ok(len) => (TokenKind::Str(&rest[1..len - 1]), len),
+                    Err(e) => return self.fail(e, pos)
+                }
+            } else if let Some(len) = separator_len(rest) {
+                (TokenKind::Separator(&rest[..len]), len)
+            } else {
+                return self.fail(LexErrorKind::UnexpectedChar(c), pos);
+            };
+            
+            let text = self.bump(len);
+            return Some(Ok(Token { kind, pos, text }));
         }
-        None
     }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, LexError>;
     
-    fn r_sep(input: &str) -> Option<(Token, usize, usize)> {
-        let re = Regex::new(r"^(:=)|(>=)|(<=)|(,)|(.)|(;)|(=)|(>)|(<)|(\+)|(-)|(\*)|(/)|(#)|(!)|(\()|(\))").unwrap();
-        
-        if let Some((start, end)) = re.find(input) {
-            return Some((Token::Separator(&input[start..end]), start, end));
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_token()
+    }
+}
+
+/// Lexes a `BufRead` a line at a time, so the whole source never has to be
+/// in memory. Only a block comment spanning several lines is buffered in
+/// full. Tokens are owned, since the line they came from is discarded.
+pub struct ReadLexer<R> {
+    reader: R,
+    options: LexerOptions,
+    // Whole lines that have been read but not lexed yet.
+    buf: String,
+    // Where `buf` starts.
+    pos: Pos,
+    // A block comment that `buf` starts with and that is still open.
+    open_comment: Option<BlockComment>,
+    pending: VecDeque<OwnedToken>,
+    // `ident_key_string` of an identifier -> spelling of its first occurrence.
+    ident_spellings: HashMap<String, String>,
+    done: bool,
+}
+
+impl<R: BufRead> ReadLexer<R> {
+    pub fn new(reader: R, options: LexerOptions) -> Self {
+        ReadLexer {
+            reader,
+            options,
+            buf: String::new(),
+            pos: Pos { line: 1, col: 1 },
+            open_comment: None,
+            pending: VecDeque::new(),
+            ident_spellings: HashMap::new(),
+            done: false
         }
-        None
     }
     
-    fn r_whitespace(input: &str) -> Option<(usize, usize)> {
-        let re = Regex::new(r"^\s+").unwrap();
-        
-        re.find(input)
+    // Appends the next line to `buf`, false at the end of the input.
+    fn read_line(&mut self) -> Result<bool, LexError> {
+        match self.reader.read_line(&mut self.buf) {
+            Ok(n) => Ok(n > 0),
+            Err(e) => Err(LexError { kind: LexErrorKind::Io(e.to_string()), pos: self.pos })
+        }
     }
     
-    let mut ret = vec![];
-    
-    let mut curr_idx: usize = 0;
-    let mut curr_str = &input[curr_idx..];
+    // Removes the first `len` bytes of `buf`.
+    fn consume(&mut self, len: usize) {
+        self.pos.advance(&self.buf[..len]);
+        self.buf.drain(..len);
+    }
     
-    let f1 = &r_ident_keyword;
-    let f2 = &r_number;
-    let f3 = &r_sep;
-    let m_funcs = {
-        let mut m_funcs: Vec<&Fn(&str) -> Option<(Token, usize, usize)>> = Vec::new();
-        m_funcs.push(f1);
-        m_funcs.push(f2);
-        m_funcs.push(f3);
+    // Scans the rest of a block comment that was open at the end of the last
+    // line, picking up where that scan stopped.
+    fn close_comment(&mut self, at_eof: bool) -> Result<bool, LexError> {
+        let mut comment = match self.open_comment.take() {
+            Some(comment) => comment,
+            None => return Ok(true)
+        };
+        if !comment.scan(&self.buf) {
+            if at_eof {
+                return Err(LexError { kind: LexErrorKind::UnterminatedComment, pos: self.pos });
+            }
+            self.open_comment = Some(comment);
+            return Ok(false);
+        }
         
-        m_funcs
-    };
+        if self.options.keep_comments {
+            let text = self.buf[..comment.idx].to_string();
+            self.pending.push_back(OwnedToken { kind: TokenKind::Comment(text.clone()), pos: self.pos, text });
+        }
+        self.consume(comment.idx);
+        Ok(true)
+    }
     
-    while !curr_str.is_empty() {
-        let mut progressed = false;
+    // Lexes the buffered lines into `pending`. A block comment that is still
+    // open at the end of the buffer stays buffered, and its scan resumes once
+    // more lines are read.
+    fn lex_buffer(&mut self, at_eof: bool) -> Result<(), LexError> {
+        if !self.close_comment(at_eof)? {
+            return Ok(());
+        }
         
-        if let Some((_, non_empty)) = r_whitespace(curr_str) {
-            curr_idx += non_empty;
-            curr_str = &input[curr_idx..];
+        let options = LexerOptions { case_insensitive_idents: false, ..self.options };
+        let mut tokens = vec![];
+        let mut lexer = Lexer::starting_at(&self.buf, options, self.pos);
+        let mut lexed = self.buf.len();
+        loop {
+            let start = lexer.idx;
+            match lexer.next_token() {
+                Some(Ok(token)) => tokens.push(token.into_owned()),
+                Some(Err(LexError { kind: LexErrorKind::UnterminatedComment, .. })) if !at_eof => {
+                    lexed = start;
+                    break;
+                }
+                Some(Err(e)) => return Err(e),
+                None => break
+            }
         }
         
-        for m_func in &m_funcs {
-            if let Some((token, _, n_start)) = m_func(curr_str) {
-                curr_idx += n_start;
-                curr_str = &input[curr_idx..];
-                ret.push(token);
-                progressed = true;
+        // What is left starts with whitespace and any discarded comments
+        // before the open one.
+        self.consume(lexed);
+        while !self.buf.is_empty() {
+            let spaces = self.buf.find(|c: char| !c.is_whitespace()).unwrap_or(self.buf.len());
+            self.consume(spaces);
+            let mut comment = BlockComment::start(&self.buf).expect("an open block comment");
+            if !comment.scan(&self.buf) {
+                self.open_comment = Some(comment);
                 break;
             }
+            self.consume(comment.idx);
         }
         
-        if !progressed {
-            return Err("can not progress".to_string());
+        for mut token in tokens {
+            if let TokenKind::Ident(ref mut value) = token.kind {
+                let fold_case = self.options.case_insensitive_idents;
+                if fold_case || !value.is_ascii() {
+                    let key = ident_key_string(value, fold_case);
+                    let spelling = self.ident_spellings.entry(key).or_insert_with(|| value.clone());
+                    value.clone_from(spelling);
+                }
+            }
+            self.pending.push_back(token);
         }
+        Ok(())
     }
+}
+
+impl<R: BufRead> Iterator for ReadLexer<R> {
+    type Item = Result<OwnedToken, LexError>;
     
-    Ok(ret)
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(token) = self.pending.pop_front() {
+                return Some(Ok(token));
+            }
+            if self.done {
+                return None;
+            }
+            
+            let result = self.read_line().and_then(|more| {
+                if !more {
+                    self.done = true;
+                }
+                self.lex_buffer(!more)
+            });
+            if let Err(e) = result {
+                self.done = true;
+                self.pending.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+pub fn r_lexer(input: &str) -> Result<Vec<Token<'_>>, LexError> {
+    r_lexer_with(input, LexerOptions::default())
+}
+
+pub fn r_lexer_with(input: &str, options: LexerOptions) -> Result<Vec<Token<'_>>, LexError> {
+    Lexer::new(input, options).collect()
 }
 
 #[test]
@@ -208,4 +810,221 @@ BEGIN
 END.");
 
     assert!(tokens.is_ok());
-}
\ No newline at end of file
+}
+#[test]
+fn test_r_lexer_string() {
+    let tokens = r_lexer(r#"! "a \"b\"\n", x"#).unwrap();
+
+    match tokens[1].kind {
+        TokenKind::Str(raw) => {
+            assert_eq!(unescape_string(raw), "a \"b\"\n");
+            assert_eq!(escape_string(&unescape_string(raw)), raw);
+        }
+        _ => panic!("expected a string token, got {:?}", tokens[1])
+    }
+
+    assert!(r_lexer(r#"! "abc"#).is_err());
+    assert!(r_lexer(r#"! "a\qc""#).is_err());
+}
+
+#[test]
+fn test_r_lexer_case_insensitive() {
+    let source = "var Foo; begin foo := FOO end.";
+
+    let tokens = r_lexer(source).unwrap();
+    assert!(matches!(tokens[0].kind, TokenKind::Ident("var")));
+
+    let options = LexerOptions { case_insensitive_keywords: true, ..LexerOptions::default() };
+    let tokens = r_lexer_with(source, options).unwrap();
+    assert!(matches!(tokens[0].kind, TokenKind::Keyword("VAR")));
+    assert_eq!(tokens[0].text, "var");
+    assert!(matches!(tokens[4].kind, TokenKind::Ident("foo")));
+
+    let options = LexerOptions { case_insensitive_keywords: true, case_insensitive_idents: true, ..LexerOptions::default() };
+    let tokens = r_lexer_with(source, options).unwrap();
+    assert!(matches!(tokens[4].kind, TokenKind::Ident("Foo")));
+    assert!(matches!(tokens[6].kind, TokenKind::Ident("Foo")));
+    assert_eq!(tokens[6].text, "FOO");
+    assert_eq!(tokens[6].pos, Pos { line: 1, col: 23 });
+}
+
+#[test]
+fn test_r_lexer_comments() {
+    let source = "{ header { nested } }
+VAR x; (* a (* nested *) note *)
+BEGIN x := 1 // trailing
+END. ";
+
+    let tokens = r_lexer(source).unwrap();
+    assert_eq!(tokens.len(), 9);
+    assert_eq!(tokens[0].pos, Pos { line: 2, col: 1 });
+
+    let options = LexerOptions { keep_comments: true, ..LexerOptions::default() };
+    let tokens = r_lexer_with(source, options).unwrap();
+    let comments: Vec<&str> = tokens.iter().filter_map(|t| match t.kind {
+        TokenKind::Comment(c) => Some(c),
+        _ => None
+    }).collect();
+    assert_eq!(comments, ["{ header { nested } }", "(* a (* nested *) note *)", "// trailing"]);
+
+    assert_eq!(r_lexer("VAR x; { open { closed }").unwrap_err().to_string(), "1:8: unterminated comment");
+    assert!(r_lexer("(* never closed").is_err());
+}
+
+#[test]
+fn test_r_lexer_errors() {
+    assert_eq!(r_lexer("x := 1 @ 2").unwrap_err().to_string(), "1:8: unexpected character `@`");
+    assert_eq!(r_lexer("x := 99999999999999999999").unwrap_err().kind, LexErrorKind::NumberOutOfRange(IntWidth::I32));
+    assert!(r_lexer("x := 1 ? y").is_ok());
+    assert_eq!(r_lexer("x := 2147483648").unwrap_err().to_string(), "1:6: number literal does not fit in 32 bits");
+    assert_eq!(r_lexer("x := 0x1__0").unwrap_err().to_string(), "1:6: malformed number literal");
+    assert_eq!(r_lexer("x := 1_").unwrap_err().kind, LexErrorKind::InvalidNumber);
+    assert_eq!(r_lexer("x := 0b").unwrap_err().kind, LexErrorKind::InvalidNumber);
+}
+
+// Run with `cargo test --release -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_r_lexer_throughput() {
+    use std::time::Instant;
+
+    let unit = "
+PROCEDURE gcd;
+VAR f, g;
+BEGIN { Euclid }
+  f := x; g := y;
+  WHILE f # g DO BEGIN
+    IF f < g THEN g := g - f;
+    IF g < f THEN f := f - g (* subtract *)
+  END;
+  ! \"gcd = \", f
+END;
+";
+    let source = unit.repeat(8 * 1024 * 1024 / unit.len());
+
+    let start = Instant::now();
+    let tokens = r_lexer(&source).unwrap();
+    let secs = start.elapsed().as_secs_f64();
+
+    let mb = source.len() as f64 / (1024.0 * 1024.0);
+    println!("lexed {:.1} MB ({} tokens) in {:.3}s: {:.1} MB/s", mb, tokens.len(), secs, mb / secs);
+}
+
+#[test]
+fn test_lexer_iterator() {
+    let mut lexer = Lexer::new("x := 1; @ y", LexerOptions::default());
+
+    assert!(matches!(lexer.next(), Some(Ok(Token { kind: TokenKind::Ident("x"), .. }))));
+    assert_eq!(lexer.by_ref().take(3).filter(|t| t.is_ok()).count(), 3);
+    assert_eq!(lexer.next().unwrap().unwrap_err().kind, LexErrorKind::UnexpectedChar('@'));
+    assert!(lexer.next().is_none());
+}
+
+#[test]
+fn test_read_lexer() {
+    use std::io::Cursor;
+
+    let source = "VAR Foo; (* spans
+  two lines *) BEGIN
+  foo := 1
+END.
+";
+    let options = LexerOptions { case_insensitive_idents: true, keep_comments: true, ..LexerOptions::default() };
+    let streamed: Vec<OwnedToken> = ReadLexer::new(Cursor::new(source), options)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let whole: Vec<OwnedToken> = r_lexer_with(source, options).unwrap()
+        .into_iter()
+        .map(Token::into_owned)
+        .collect();
+
+    assert_eq!(streamed, whole);
+    assert_eq!(streamed[5].kind, TokenKind::Ident("Foo".to_owned()));
+    assert_eq!(streamed[5].pos, Pos { line: 3, col: 3 });
+
+    let mut broken = ReadLexer::new(Cursor::new("VAR x;\n{ never\nclosed\n"), LexerOptions::default());
+    assert!(broken.next().unwrap().is_ok());
+    assert!(broken.next().unwrap().is_ok());
+    assert!(broken.next().unwrap().is_ok());
+    assert_eq!(broken.next().unwrap().unwrap_err().to_string(), "2:1: unterminated comment");
+    assert!(broken.next().is_none());
+}
+
+#[test]
+fn test_read_lexer_long_comment() {
+    use std::io::Cursor;
+
+    // Rescanning the open comment on every line would take minutes here.
+    let lines = 100_000;
+    let source = format!("VAR x; (* {{ (* nested *)\n{}*) x := 1 {{ a\n{{ b }}\n}}.\n", "comment line\n".repeat(lines));
+    let options = LexerOptions { keep_comments: true, ..LexerOptions::default() };
+    let streamed: Vec<OwnedToken> = ReadLexer::new(Cursor::new(&source), options)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let whole: Vec<OwnedToken> = r_lexer_with(&source, options).unwrap()
+        .into_iter()
+        .map(Token::into_owned)
+        .collect();
+
+    assert_eq!(streamed, whole);
+    assert_eq!(streamed.len(), 9);
+    assert_eq!(streamed[4].pos, Pos { line: lines + 2, col: 4 });
+
+    // A discarded comment before the open one must not be taken for it.
+    let source = "x {} {\n";
+    let streamed: Result<Vec<_>, _> = ReadLexer::new(Cursor::new(source), LexerOptions::default()).collect();
+    let whole = r_lexer(source).map(|tokens| tokens.into_iter().map(Token::into_owned).collect::<Vec<_>>());
+    assert_eq!(streamed, whole);
+    assert!(streamed.is_err());
+}
+
+#[test]
+fn test_r_lexer_unicode_idents() {
+    // The second `café` spells é as e + U+0301 COMBINING ACUTE ACCENT.
+    let source = "VAR größe, _tmp1, café, δέλτα; BEGIN cafe\u{301} := größe END.";
+    let tokens = r_lexer(source).unwrap();
+
+    let idents: Vec<&str> = tokens.iter().filter_map(|t| match t.kind {
+        TokenKind::Ident(id) => Some(id),
+        _ => None
+    }).collect();
+    assert_eq!(idents, ["größe", "_tmp1", "café", "δέλτα", "café", "größe"]);
+    assert_eq!(tokens[10].text, "cafe\u{301}");
+
+    assert!(matches!(r_lexer("1abc").unwrap()[..], [Token { kind: TokenKind::Number(1, _), .. }, Token { kind: TokenKind::Ident("abc"), .. }]));
+
+    let options = LexerOptions { case_insensitive_idents: true, ..LexerOptions::default() };
+    let tokens = r_lexer_with("ΔΈΛΤΑ δέλτα", options).unwrap();
+    assert!(matches!(tokens[1].kind, TokenKind::Ident("ΔΈΛΤΑ")));
+
+    let options = LexerOptions { max_ident_len: Some(10), ..LexerOptions::default() };
+    assert!(r_lexer_with("abcdefghij", options).is_ok());
+    assert_eq!(r_lexer_with("x := abcdefghijk", options).unwrap_err().to_string(),
+               "1:6: identifier longer than 10 characters");
+}
+
+#[test]
+fn test_r_lexer_number_formats() {
+    let numbers = |source| r_lexer(source).unwrap().iter().filter_map(|t| match t.kind {
+        TokenKind::Number(n, radix) => Some((n, radix)),
+        _ => None
+    }).collect::<Vec<_>>();
+    
+    assert_eq!(numbers("0x1F 0XfF 0b1010 1_000_000 0"),
+               [(31, Radix::Hex), (255, Radix::Hex), (10, Radix::Binary), (1_000_000, Radix::Decimal), (0, Radix::Decimal)]);
+    assert_eq!(numbers("0x7FFF_FFFF"), [(i32::MAX as i64, Radix::Hex)]);
+    
+    let options = LexerOptions { int_width: IntWidth::I64, ..LexerOptions::default() };
+    assert!(r_lexer_with("x := 9_223_372_036_854_775_807", options).is_ok());
+    assert_eq!(r_lexer_with("x := 0x8000000000000000", options).unwrap_err().kind,
+               LexErrorKind::NumberOutOfRange(IntWidth::I64));
+    
+    for source in ["0x1F", "0b1010", "1000000"].iter() {
+        let formatted = numbers(source).iter().map(|&(n, radix)| format_number(n, radix)).collect::<Vec<_>>();
+        assert_eq!(formatted, [*source]);
+    }
+    assert_eq!(format_number(1_000_000, Radix::Decimal), "1000000");
+    assert_eq!(format_number(-5, Radix::Hex), "-0x5");
+    assert_eq!(format_number(-10, Radix::Binary), "-0b1010");
+    assert_eq!(format_number(i64::MIN, Radix::Hex), "-0x8000000000000000");
+}
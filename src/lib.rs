@@ -0,0 +1,136 @@
+//! PL/0 as a library, for embedding it as a scripting language.
+//!
+//! `compile` turns source into a `Program`, which can be run any number of
+//! times against an `Io`. `compile_with_natives` also lets the program call
+//! procedures and functions written in Rust. These items at the crate root
+//! are the stable API. The modules below are public for the `pl0` binary,
+//! tools and fuzzing, and may change.
+//!
+//! There is no bytecode yet: programs are run by walking the AST.
+
+extern crate serde;
+extern crate serde_json;
+extern crate unicode_normalization;
+extern crate unicode_xid;
+
+pub mod ast;
+pub mod check;
+pub mod differential;
+pub mod dump;
+pub mod format;
+pub mod generate;
+pub mod graph;
+pub mod lexer;
+pub mod parser;
+mod codegen;
+pub mod interpreter;
+pub mod symbol;
+pub mod visit;
+
+pub use ast::SyntaxError;
+pub use check::SemanticError;
+pub use interpreter::{Io, Limit, Limits, MemoryIo, Natives, Overflow, RunMode, RuntimeError, StdIo};
+pub use lexer::{IntWidth, LexError, LexerOptions};
+pub use symbol::SymbolTable;
+
+use interpreter::Interpreter;
+use std::error::Error;
+use std::fmt;
+
+/// One problem found while compiling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    Lexical(LexError),
+    Syntax(SyntaxError),
+    Semantic(SemanticError),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Diagnostic::Lexical(ref e) => write!(f, "lexical error: {}", e),
+            Diagnostic::Syntax(ref e) => write!(f, "syntax error: {}", e),
+            Diagnostic::Semantic(ref e) => write!(f, "semantic error: {}", e),
+        }
+    }
+}
+
+/// Everything that kept a source from compiling, in source order. Lexing
+/// stops at its first error. Syntax errors are all reported, and so are
+/// semantic errors once the source parses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    pub errors: Vec<Diagnostic>,
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for e in &self.errors {
+            writeln!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Diagnostics {}
+
+/// A compiled program.
+pub struct Program {
+    interpreter: Interpreter,
+    symbols: SymbolTable,
+}
+
+impl Program {
+    pub fn ast(&self) -> &ast::Program {
+        self.interpreter.program()
+    }
+    
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+    
+    /// Integer width and overflow behaviour for later runs. The width
+    /// starts out as the one the source was lexed with.
+    pub fn set_mode(&mut self, mode: RunMode) {
+        self.interpreter.set_mode(mode);
+    }
+    
+    /// Bounds on later runs, for programs that cannot be trusted to stop.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.interpreter.set_limits(limits);
+    }
+    
+    /// Runs the program from the start, reading `?` input from and writing
+    /// `!` output to `io`.
+    pub fn run(&self, io: &mut dyn Io) -> Result<(), RuntimeError> {
+        self.interpreter.run_with(io)
+    }
+}
+
+pub fn compile(source: &str) -> Result<Program, Diagnostics> {
+    compile_with(source, LexerOptions::default())
+}
+
+pub fn compile_with(source: &str, options: LexerOptions) -> Result<Program, Diagnostics> {
+    compile_with_natives(source, options, Natives::new())
+}
+
+/// Compiles a program that may call `natives`, as if they were declared
+/// around it.
+pub fn compile_with_natives(source: &str, options: LexerOptions, natives: Natives) -> Result<Program, Diagnostics> {
+    let tokens = lexer::r_lexer_with(source, LexerOptions { keep_comments: false, ..options })
+        .map_err(|e| Diagnostics { errors: vec![Diagnostic::Lexical(e)] })?;
+    let ast = parser::parse_program(&tokens)
+        .map_err(|failure| Diagnostics { errors: failure.errors.into_iter().map(Diagnostic::Syntax).collect() })?;
+    
+    let symbols = SymbolTable::new(&ast);
+    let errors = check::check(&ast, &symbols, &natives);
+    if !errors.is_empty() {
+        return Err(Diagnostics { errors: errors.into_iter().map(Diagnostic::Semantic).collect() });
+    }
+    
+    let mut interpreter = Interpreter::new(ast);
+    interpreter.set_mode(RunMode { width: options.int_width, ..RunMode::default() });
+    interpreter.set_natives(natives);
+    Ok(Program { interpreter, symbols })
+}
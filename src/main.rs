@@ -1,22 +1,190 @@
-#[macro_use]
-extern crate chomp;
-extern crate regex;
+extern crate pl0;
 
-use chomp::*;
+use pl0::ast::{Program, SyntaxError};
+use pl0::format::*;
+use pl0::lexer::*;
+use pl0::parser::*;
+use pl0::interpreter::*;
+use pl0::symbol::SymbolTable;
+use pl0::{check, differential, dump, generate, graph};
+use std::env;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+use std::process::ExitCode;
+use std::str::FromStr;
+use std::time::Duration;
 
-mod lexer;
-mod parser;
-mod codegen;
-mod interpreter;
+#[derive(PartialEq)]
+enum Command {
+    Run,
+    Fmt,
+    Parse,
+    Graph,
+    Diff,
+    Generate,
+}
 
-use lexer::*;
-use parser::*;
-use interpreter::*;
+enum Emit {
+    Json,
+    Sexp,
+}
 
-fn main() {
+struct Args {
+    command: Command,
+    path: Option<String>,
+    lexer: LexerOptions,
+    mode: RunMode,
+    limits: Limits,
+    format: FormatOptions,
+    emit: Emit,
+    cfg: bool,
+    input: Option<String>,
+    seed: u64,
+    generator: generate::GeneratorOptions,
+    dump_tokens: bool,
+}
 
-  
-    let tokens = r_lexer("
+// `pl0 [fmt|parse|graph|diff] [FILE]` followed by any of `--int-width=32|64`,
+// `--overflow=checked|wrapping|saturating`, `--case-insensitive-keywords`,
+// `--case-insensitive-idents`, `--max-ident-len=N`, `--tokens`,
+// `--indent=N`, `--one-decl-per-line`, `--emit=json|sexp`, `--ast|--cfg`,
+// `--input=FILE`, `--max-steps=N`, `--max-call-depth=N` and `--timeout=MS`,
+// or `pl0 generate [--seed=N] [--size=N]`. A number that does not parse
+// is a usage error.
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        command: Command::Run,
+        path: None,
+        lexer: LexerOptions::default(),
+        mode: RunMode::default(),
+        limits: Limits::default(),
+        format: FormatOptions::default(),
+        emit: Emit::Json,
+        cfg: false,
+        input: None,
+        seed: 0,
+        generator: generate::GeneratorOptions::default(),
+        dump_tokens: false
+    };
+    
+    for (i, arg) in env::args().skip(1).enumerate() {
+        match arg.as_str() {
+            "fmt" if i == 0 => args.command = Command::Fmt,
+            "parse" if i == 0 => args.command = Command::Parse,
+            "--emit=json" => args.emit = Emit::Json,
+            "--emit=sexp" => args.emit = Emit::Sexp,
+            "graph" if i == 0 => args.command = Command::Graph,
+            "diff" if i == 0 => args.command = Command::Diff,
+            "generate" if i == 0 => args.command = Command::Generate,
+            "--ast" => args.cfg = false,
+            "--cfg" => args.cfg = true,
+            "--int-width=32" => {
+                args.mode.width = IntWidth::I32;
+                args.lexer.int_width = IntWidth::I32;
+            }
+            "--int-width=64" => {
+                args.mode.width = IntWidth::I64;
+                args.lexer.int_width = IntWidth::I64;
+            }
+            "--overflow=checked" => args.mode.overflow = Overflow::Checked,
+            "--overflow=wrapping" => args.mode.overflow = Overflow::Wrapping,
+            "--overflow=saturating" => args.mode.overflow = Overflow::Saturating,
+            "--case-insensitive-keywords" => args.lexer.case_insensitive_keywords = true,
+            "--case-insensitive-idents" => args.lexer.case_insensitive_idents = true,
+            "--tokens" => args.dump_tokens = true,
+            "--one-decl-per-line" => args.format.one_decl_per_line = true,
+            _ if arg.starts_with("--indent=") => args.format.indent = number(&arg, "--indent=")?,
+            _ if arg.starts_with("--input=") => args.input = Some(arg["--input=".len()..].to_string()),
+            _ if arg.starts_with("--seed=") => args.seed = number(&arg, "--seed=")?,
+            _ if arg.starts_with("--size=") => args.generator.max_items = number(&arg, "--size=")?,
+            _ if arg.starts_with("--max-steps=") => args.limits.max_steps = Some(number(&arg, "--max-steps=")?),
+            _ if arg.starts_with("--max-call-depth=") => args.limits.max_call_depth = Some(number(&arg, "--max-call-depth=")?),
+            _ if arg.starts_with("--timeout=") => args.limits.timeout = Some(Duration::from_millis(number(&arg, "--timeout=")?)),
+            _ if arg.starts_with("--max-ident-len=") => args.lexer.max_ident_len = Some(number(&arg, "--max-ident-len=")?),
+            _ if !arg.starts_with("--") && args.path.is_none() => args.path = Some(arg),
+            _ => eprintln!("ignoring unknown argument {}", arg)
+        }
+    }
+    Ok(args)
+}
+
+// The number after `flag` in `arg`, e.g. 42 in `--seed=42`.
+fn number<T: FromStr>(arg: &str, flag: &str) -> Result<T, String> {
+    arg[flag.len()..].parse().map_err(|_| format!("invalid number in {}", arg))
+}
+
+fn read_source(path: Option<&str>) -> io::Result<String> {
+    match path {
+        Some(path) => fs::read_to_string(path),
+        None => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source)?;
+            Ok(source)
+        }
+    }
+}
+
+// Lexes and parses `source`, reporting every error on stderr. The comments are
+// returned alongside the program. With `partial`, a program with syntax
+// errors is still returned, holding error nodes.
+fn parse_source(source: &str, options: LexerOptions, partial: bool) -> Option<(Program, Vec<Token<'_>>)> {
+    let tokens = match r_lexer_with(source, LexerOptions { keep_comments: true, ..options }) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            eprintln!("lexical error: {}", e);
+            return None;
+        }
+    };
+    let (comments, tokens): (Vec<Token>, Vec<Token>) = tokens.into_iter()
+        .partition(|t| matches!(t.kind, TokenKind::Comment(_)));
+    
+    match parse_program(&tokens) {
+        Ok(program) => Some((program, comments)),
+        Err(failure) => {
+            report_syntax_errors(&failure.errors);
+            if partial { Some((*failure.partial, comments)) } else { None }
+        }
+    }
+}
+
+fn report_syntax_errors(errors: &[SyntaxError]) {
+    for e in errors {
+        eprintln!("syntax error: {}", e);
+    }
+}
+
+// Streams the file, or stdin without one, through the lexer, one token per
+// line.
+fn dump_tokens(path: Option<&str>, options: LexerOptions) -> ExitCode {
+    let stdin = io::stdin();
+    let reader: Box<dyn BufRead> = match path {
+        Some(path) => match fs::File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("cannot read source: {}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        None => Box::new(stdin.lock())
+    };
+    
+    for token in ReadLexer::new(reader, options) {
+        match token {
+            Ok(OwnedToken { kind: TokenKind::Number(n, radix), pos, .. }) => {
+                println!("{}\tNumber({})", pos, format_number(n, radix))
+            }
+            Ok(token) => println!("{}\t{:?}", token.pos, token.kind),
+            Err(e) => {
+                eprintln!("lexical error: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+const DEMO: &str = "
     
 CONST
   m =  7,
@@ -85,20 +253,127 @@ BEGIN
   CALL gcd;
   
   !z;
-END.");
+END.";
 
-    // println!("tokens = {:?}", tokens);
+// Diagnostics go to stderr, and any of them makes the exit status nonzero.
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("usage error: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    
+    if args.dump_tokens {
+        return dump_tokens(args.path.as_deref(), LexerOptions { keep_comments: true, ..args.lexer });
+    }
+    
+    // Prints a random program, e.g. for `pl0 generate --seed=N | pl0 diff`.
+    if args.command == Command::Generate {
+        let program = generate::generate(args.seed, args.generator);
+        print!("{}", format_program(&program, &[], args.format));
+        return ExitCode::SUCCESS;
+    }
+  
+    // Without a file, tools read stdin and running falls back to the demo.
+    let source = match (args.path.as_deref(), &args.command) {
+        (None, &Command::Run) => Ok(DEMO.to_string()),
+        (path, _) => read_source(path)
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("cannot read source: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    
+    // A `.json` file holds an AST written by `pl0 parse --emit=json`.
+    let parsed = if args.path.as_ref().is_some_and(|p| p.ends_with(".json")) {
+        match dump::from_json(&source) {
+            // Hand-written ASTs may still hold error nodes, or literals the
+            // lexer would have rejected.
+            Ok(program) => {
+                let literals = dump::check_literals(&program, args.lexer.int_width);
+                for e in &literals {
+                    eprintln!("invalid AST: {}", e);
+                }
+                let errors = syntax_errors(&program);
+                report_syntax_errors(&errors);
+                let usable = errors.is_empty() || args.command == Command::Parse;
+                if literals.is_empty() && usable { Some((program, Vec::new())) } else { None }
+            }
+            Err(e) => {
+                eprintln!("invalid AST: {}", e);
+                None
+            }
+        }
+    } else {
+        parse_source(&source, args.lexer, args.command == Command::Parse)
+    };
+    let (program, comments) = match parsed {
+        Some(parsed) => parsed,
+        None => return ExitCode::FAILURE
+    };
+    // Only `parse` gets this far with syntax errors.
+    let status = if syntax_errors(&program).is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE };
     
-    if let Ok(tokens) = tokens {
-        let ast = parse_only(program, &tokens);
-        
-        if let Ok(c) = ast {
-            // println!("c = {:?}", c);
-            
-            let interpreter = Interpreter::new(c);
-            let ret = interpreter.run();
-            println!("ret = {:?}", ret);
+    if args.command == Command::Run || args.command == Command::Diff {
+        let errors = check::check(&program, &SymbolTable::new(&program), &Natives::new());
+        for e in &errors {
+            eprintln!("semantic error: {}", e);
+        }
+        if !errors.is_empty() {
+            return ExitCode::FAILURE;
         }
     }
     
+    match args.command {
+        Command::Fmt => print!("{}", format_program(&program, &comments, args.format)),
+        Command::Parse => match args.emit {
+            Emit::Json => println!("{}", dump::to_json(&program)),
+            Emit::Sexp => println!("{}", dump::to_sexp(&program)),
+        },
+        Command::Graph if args.cfg => print!("{}", graph::cfg_to_dot(&program)),
+        Command::Graph => print!("{}", graph::ast_to_dot(&program)),
+        Command::Generate => unreachable!(),
+        // Runs the program through every engine with the same input.
+        Command::Diff => {
+            let input = match args.input.as_ref().map(fs::read_to_string) {
+                Some(Ok(input)) => input,
+                Some(Err(e)) => {
+                    eprintln!("cannot read input: {}", e);
+                    return ExitCode::FAILURE;
+                }
+                None => String::new()
+            };
+            let engines = differential::engines();
+            match differential::compare(&program, &input, args.mode, args.limits, &engines) {
+                Ok(_) => {
+                    let names: Vec<&str> = engines.iter().map(|e| e.name()).collect();
+                    println!("no divergence between {}", names.join(", "));
+                }
+                Err(divergence) => {
+                    print!("{}", divergence);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        Command::Run => {
+            let mut interpreter = Interpreter::new(program);
+            interpreter.set_mode(args.mode);
+            interpreter.set_limits(args.limits);
+            match interpreter.run() {
+                Ok(()) => {}
+                // Whatever read the output has stopped, e.g. `pl0 | head`.
+                Err(RuntimeError::Io(_, io::ErrorKind::BrokenPipe, _)) => {}
+                Err(e) => {
+                    eprintln!("runtime error: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+    }
+    status
 }
\ No newline at end of file
@@ -1,509 +1,872 @@
-use chomp::*;
+//! Recursive descent over the token stream, with precedence climbing for
+//! expressions.
+//!
+//! Errors inside a statement or declaration leave an error node in its
+//! place; parsing picks up again after the next `;`, `END`, `PROCEDURE` or
+//! `.`, so one run reports every error it can find.
+
+use ast::*;
 use lexer::*;
+use symbol::Symbol;
+use visit::{walk_decl, walk_stmt, Visitor};
 
-#[derive(Debug, Clone)]
-pub enum Sign {
-    Plus,
-    Minus
-}
-
-#[derive(Debug, Clone)]
-pub enum BiOp {
-    Mul,
-    Div
-}
+type ParseResult<T> = Result<T, SyntaxError>;
 
-#[derive(Debug, Clone)]
-pub enum ExOp {
-    Equal,
-    NumberSign,
-    LessThan,
-    LessThanOrEqual,
-    GreaterThan,
-    GreaterThanOrEqual,
-}
+// Deeper nesting is an error rather than a stack overflow, here and in
+// every later pass over the tree. A chain of left-associative operators
+// nests one level per operator.
+const MAX_NESTING: usize = 100;
 
-#[derive(Debug, Clone)]
-pub enum AstNode<'a> {
-    Number(i32),
-    Ident(&'a str),
-    Factor(Box<AstNode<'a>>),
-    Term {factors: Vec<AstNode<'a>>, ops: Vec<BiOp>},
-    Expression {terms: Vec<AstNode<'a>>, signs: Vec<Sign>},
-    Odd(Box<AstNode<'a>>),
-    ComposedExpression {ex1: Box<AstNode<'a>>, op: ExOp, ex2: Box<AstNode<'a>>},
-    BeginEnd(Vec<AstNode<'a>>),
-    IfThen {condition: Box<AstNode<'a>>, statement: Box<AstNode<'a>>},
-    WhileDo {condition: Box<AstNode<'a>>, statement: Box<AstNode<'a>>},
-    Assignment {ident: Box<AstNode<'a>>, expression: Box<AstNode<'a>>},
-    Call(Box<AstNode<'a>>),
-    QuestionMark(Box<AstNode<'a>>),
-    ExclaimationMark(Box<AstNode<'a>>),
-    Const {ident: Box<AstNode<'a>>, value: Box<AstNode<'a>>},
-    Procedure {ident: Box<AstNode<'a>>, block: Box<AstNode<'a>>},
-    Block {const_decl: Vec<AstNode<'a>>, var_decl: Vec<AstNode<'a>>, procedures: Vec<AstNode<'a>>, statement: Box<AstNode<'a>>}
+fn token_span(token: &Token) -> Span {
+    let mut end = token.pos;
+    for (i, c) in token.text.chars().enumerate() {
+        if i == 0 {
+            continue;
+        }
+        if c == '\n' {
+            end.line += 1;
+            end.col = 1;
+        } else {
+            end.col += 1;
+        }
+    }
+    Span { start: token.pos, end }
 }
 
-fn token_separator_cotent<'a>(tok: Token<'a>) -> Option<&'a str> {
-    match tok {
-        Token::Separator(tc) => {
-            Some(tc)
-        },
+// Binary operators with their binding power.
+fn binary_op(token: &Token) -> Option<(BinOp, u8)> {
+    match token.kind {
+        TokenKind::Separator("+") => Some((BinOp::Add, 1)),
+        TokenKind::Separator("-") => Some((BinOp::Sub, 1)),
+        TokenKind::Separator("*") => Some((BinOp::Mul, 2)),
+        TokenKind::Separator("/") => Some((BinOp::Div, 2)),
+        TokenKind::Separator("%") | TokenKind::Keyword("MOD") => Some((BinOp::Mod, 2)),
         _ => None
     }
 }
 
-fn token_keyword_cotent<'a>(tok: Token<'a>) -> Option<&'a str> {
-    match tok {
-        Token::Keyword(tc) => {
-            Some(tc)
-        },
+fn ex_op(token: &Token) -> Option<ExOp> {
+    match token.kind {
+        TokenKind::Separator("=") => Some(ExOp::Equal),
+        TokenKind::Separator("#") => Some(ExOp::NumberSign),
+        TokenKind::Separator("<") => Some(ExOp::LessThan),
+        TokenKind::Separator("<=") => Some(ExOp::LessThanOrEqual),
+        TokenKind::Separator(">") => Some(ExOp::GreaterThan),
+        TokenKind::Separator(">=") => Some(ExOp::GreaterThanOrEqual),
         _ => None
     }
 }
 
-fn plus_sign<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, Sign> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("+"));
-
-        ret Sign::Plus
+fn starts_expression(token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Number(..) | TokenKind::Ident(_) => true,
+        TokenKind::Separator(s) => ["(", "-", "+"].contains(&s),
+        _ => false
     }
 }
 
-fn minus_sign<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, Sign> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("-"));
-
-        ret Sign::Minus
+fn starts_statement(token: &Token) -> bool {
+    match token.kind {
+        TokenKind::Ident(_) => true,
+        TokenKind::Keyword(k) => ["CALL", "BEGIN", "IF", "WHILE"].contains(&k),
+        TokenKind::Separator(s) => s == "?" || s == "!",
+        _ => false
     }
 }
 
-fn sign<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, Sign> {
-    parse!{i;
-        let e_sign = or(plus_sign, minus_sign);
-        
-        ret e_sign
-    }
+struct Parser<'t, 'a: 't> {
+    tokens: &'t [Token<'a>],
+    next: usize,
+    // How many expressions, conditions, statements and blocks enclose the
+    // next token.
+    depth: usize,
 }
 
-fn mul_sign<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, BiOp> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("*"));
-
-        ret BiOp::Mul
+impl<'t, 'a> Parser<'t, 'a> {
+    fn new(tokens: &'t [Token<'a>]) -> Self {
+        Parser { tokens, next: 0, depth: 0 }
     }
-}
-
-fn div_sign<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, BiOp> {
-    parse!{i;
-        
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("/"));
-
-        ret BiOp::Div
+    
+    fn peek(&self) -> Option<&'t Token<'a>> {
+        self.tokens.get(self.next)
     }
-}
-
-fn ex_op<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, ExOp> {
-    parse!{i;
-        less_than_or_equal()
-        <|> greater_than_or_equal()
-        <|> equal()
-        <|> number_sign()
-        <|> less_than()
-        <|> greater_than()
+    
+    fn advance(&mut self) -> Pos {
+        let pos = self.tokens[self.next].pos;
+        self.next += 1;
+        pos
     }
-}
-
-fn equal<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, ExOp> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("="));
-
-        ret ExOp::Equal
+    
+    // From the token at `start` to the last one read, which must not be
+    // before it.
+    fn span_from(&self, start: usize) -> Span {
+        Span { start: self.tokens[start].pos, end: token_span(&self.tokens[self.next - 1]).end }
     }
-}
-
-fn number_sign<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, ExOp> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("#"));
-
-        ret ExOp::NumberSign
+    
+    fn is_keyword(&self, keyword: &str) -> bool {
+        self.peek().is_some_and(|t| t.kind == TokenKind::Keyword(keyword))
     }
-}
-
-fn less_than<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, ExOp> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("<"));
-
-        ret ExOp::LessThan
+    
+    fn is_separator(&self, separator: &str) -> bool {
+        self.peek().is_some_and(|t| t.kind == TokenKind::Separator(separator))
     }
-}
-
-fn less_than_or_equal<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, ExOp> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("<="));
-
-        ret ExOp::LessThanOrEqual
+    
+    // An error at the next token.
+    fn error(&self, expected: &str) -> SyntaxError {
+        SyntaxError {
+            expected: expected.to_string(),
+            found: self.peek().map(|t| (token_span(t), t.text.to_string()))
+        }
     }
-}
-
-fn greater_than<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, ExOp> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some(">"));
-
-        ret ExOp::GreaterThan
+    
+    fn expect_keyword(&mut self, keyword: &str) -> ParseResult<Pos> {
+        if self.is_keyword(keyword) {
+            Ok(self.advance())
+        } else {
+            Err(self.error(&format!("`{}`", keyword)))
+        }
     }
-}
-
-fn greater_than_or_equal<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, ExOp> {
-    parse!{i;
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some(">="));
-
-        ret ExOp::GreaterThanOrEqual
+    
+    fn expect_separator(&mut self, separator: &str) -> ParseResult<Pos> {
+        if self.is_separator(separator) {
+            Ok(self.advance())
+        } else {
+            Err(self.error(&format!("`{}`", separator)))
+        }
     }
-}
-
-fn number<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    let n = satisfy(i,
-        |t| {
-            match t {
-                Token::Number(_) => true,
-                _ => false
-            }
-        }).map(|lc| {
-            match lc {
-                Token::Number(c) => AstNode::Number(c),
-                _ => panic!("asd")
+    
+    fn ident(&mut self) -> ParseResult<Ident> {
+        match self.peek() {
+            Some(token @ &Token { kind: TokenKind::Ident(name), .. }) => {
+                self.next += 1;
+                Ok(Ident { name: Symbol::intern(name), span: token_span(token) })
             }
-        });
-    n
-}
-
-fn ident<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    let ident = satisfy(i, |t| {
-        match t {
-            Token::Ident(_) => true,
-            _ => false 
-        }
-    }).map(|lc| {
-        match lc {
-            Token::Ident(id) => AstNode::Ident(id),
-            _ => panic!("asd")
+            _ => Err(self.error("identifier"))
         }
-    });
-
-    ident
-}
-
-fn factor<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    fn grouped_expression<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-        
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("("));
-            
-            let e = expression();
-            
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some(")"));
-            
-            ret e
+    }
+    
+    fn number(&mut self) -> ParseResult<(Span, i64, Radix)> {
+        match self.peek() {
+            Some(token @ &Token { kind: TokenKind::Number(n, radix), .. }) => {
+                self.next += 1;
+                Ok((token_span(token), n, radix))
+            }
+            _ => Err(self.error("number"))
         }
     }
-    fn numer_or_ident<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            
-            let r = or(number, ident);
-            
-            ret r
+    
+    fn nesting_error(&self) -> SyntaxError {
+        self.error(&format!("at most {} levels of nesting", MAX_NESTING))
+    }
+    
+    fn deeper(&mut self) -> ParseResult<()> {
+        if self.depth >= MAX_NESTING {
+            return Err(self.nesting_error());
         }
+        self.depth += 1;
+        Ok(())
     }
-    parse!{i;
-        let f = or(numer_or_ident, grouped_expression);
-        
-        ret AstNode::Factor(Box::new(f))
+    
+    // Runs `parse`, then drops whatever nesting it added.
+    fn scoped<T, F: FnOnce(&mut Self) -> ParseResult<T>>(&mut self, parse: F) -> ParseResult<T> {
+        let depth = self.depth;
+        let result = parse(self);
+        self.depth = depth;
+        result
     }
-}
-
-fn term<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    fn sub_term<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, (BiOp, AstNode<'a>)> {
-        parse!{i;
-            
-            let sign = or(mul_sign, div_sign);
-            let fa = factor();
+    
+    fn nested<T, F: FnOnce(&mut Self) -> ParseResult<T>>(&mut self, parse: F) -> ParseResult<T> {
+        self.scoped(|p| {
+            p.deeper()?;
+            parse(p)
+        })
+    }
+    
+    fn expression(&mut self) -> ParseResult<Expr> {
+        self.nested(|p| p.binary(1))
+    }
+    
+    // Operators binding at least as tightly as `min`, left-associative.
+    fn binary(&mut self, min: u8) -> ParseResult<Expr> {
+        self.scoped(|p| {
+            let mut lhs = p.operand(min)?;
             
-            ret (sign, fa)
+            while let Some((op, power)) = p.peek().and_then(binary_op) {
+                if power < min {
+                    break;
+                }
+                p.deeper()?;
+                let pos = p.advance();
+                let rhs = p.binary(power + 1)?;
+                lhs = Expr::Binary {
+                    lhs: Box::new(lhs),
+                    op,
+                    pos,
+                    rhs: Box::new(rhs)
+                };
+            }
+            Ok(lhs)
+        })
+    }
+    
+    // A sign at the start of an expression applies to the whole first term,
+    // so `-a * b` is `-(a * b)`. Anywhere else `-` negates a single factor.
+    fn operand(&mut self, min: u8) -> ParseResult<Expr> {
+        match self.peek().map(|t| &t.kind) {
+            Some(&TokenKind::Separator(sign)) if min == 1 && (sign == "+" || sign == "-") => {
+                let pos = self.advance();
+                let term = self.binary(2)?;
+                Ok(if sign == "-" { Expr::Negate(pos, Box::new(term)) } else { term })
+            }
+            _ => self.factor()
         }
     }
     
-    parse!{i;
-        let first_factor = factor();
-        
-        let sub_terms: Vec<(BiOp, AstNode<'a>)> = many(sub_term);
-        
-        ret AstNode::Term {
-            factors: {
-                let mut v = vec![first_factor];
-                for t in &sub_terms {
-                    let (_, x) = t.clone();
-                    v.push(x.clone());
-                }
-                v
-            },
-            ops: {
-                let mut v = vec![];
-                for t in &sub_terms {
-                    let (x, _) = t.clone();
-                    v.push(x.clone());
+    fn factor(&mut self) -> ParseResult<Expr> {
+        match self.peek().map(|t| &t.kind) {
+            Some(&TokenKind::Number(..)) => {
+                let (span, n, radix) = self.number()?;
+                Ok(Expr::Number(span, n, radix))
+            }
+            Some(&TokenKind::Ident(_)) => {
+                let start = self.next;
+                let name = self.ident()?;
+                if self.is_separator("(") {
+                    self.advance();
+                    let args = self.arguments()?;
+                    Ok(Expr::Call {span: self.span_from(start), name, args})
+                } else {
+                    Ok(Expr::Ident(name))
                 }
-                v
             }
+            Some(&TokenKind::Separator("(")) => {
+                self.advance();
+                let e = self.expression()?;
+                self.expect_separator(")")?;
+                Ok(e)
+            }
+            Some(&TokenKind::Separator("-")) => {
+                let pos = self.advance();
+                Ok(Expr::Negate(pos, Box::new(self.nested(Self::factor)?)))
+            }
+            _ => Err(self.error("expression"))
         }
     }
-}
-
-fn expression<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    fn sub_expression<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, (AstNode<'a>, Sign)> {
-        parse!{i;
-            
-            
-            let sign = sign();
-            let term = term();
-            ret (term, sign)
+    
+    // `[expression {"," expression}] ")"`, after the `(`.
+    fn arguments(&mut self) -> ParseResult<Vec<Expr>> {
+        let mut args = Vec::new();
+        if self.is_separator(")") {
+            self.advance();
+            return Ok(args);
         }
+        loop {
+            args.push(self.expression()?);
+            if self.is_separator(",") {
+                self.advance();
+            } else {
+                self.expect_separator(")")?;
+                return Ok(args);
+            }
+        }
+    }
+    
+    fn condition(&mut self) -> ParseResult<Cond> {
+        self.scoped(|p| {
+            let mut lhs = p.and_condition()?;
+            while p.is_keyword("OR") {
+                p.deeper()?;
+                p.advance();
+                let rhs = p.and_condition()?;
+                lhs = Cond::Logic { lhs: Box::new(lhs), op: LogicOp::Or, rhs: Box::new(rhs) };
+            }
+            Ok(lhs)
+        })
     }
     
-    parse!{i;
-        let first_sign = option(sign, Sign::Plus);
-        let first_term = term();
+    fn and_condition(&mut self) -> ParseResult<Cond> {
+        self.scoped(|p| {
+            let mut lhs = p.unary_condition()?;
+            while p.is_keyword("AND") {
+                p.deeper()?;
+                p.advance();
+                let rhs = p.unary_condition()?;
+                lhs = Cond::Logic { lhs: Box::new(lhs), op: LogicOp::And, rhs: Box::new(rhs) };
+            }
+            Ok(lhs)
+        })
+    }
     
-        let e: Vec<(AstNode<'a>, Sign)> = many(sub_expression);
-        
-        ret AstNode::Expression {
-            terms: {
-                let mut v = vec![first_term];
-                for t in &e {
-                    let (x, _) = t.clone();
-                    v.push(x.clone());
-                }
-                v
-            },
-            signs: {
-                let mut v = vec![first_sign];
-                for t in &e {
-                    let (_, x) = t.clone();
-                    v.push(x.clone());
+    fn unary_condition(&mut self) -> ParseResult<Cond> {
+        match self.peek().map(|t| &t.kind) {
+            Some(&TokenKind::Keyword("NOT")) => {
+                let pos = self.advance();
+                Ok(Cond::Not(pos, Box::new(self.nested(Self::unary_condition)?)))
+            }
+            Some(&TokenKind::Keyword("ODD")) => {
+                let pos = self.advance();
+                Ok(Cond::Odd(pos, self.expression()?))
+            }
+            // `(a + b) > c` starts with a grouped expression and `(a > b)`
+            // is a grouped condition. The comparison is tried first; if both
+            // fail, the error that got further is the one reported.
+            Some(&TokenKind::Separator("(")) => {
+                let start = self.next;
+                let compare_error = match self.comparison() {
+                    Ok(c) => return Ok(c),
+                    Err(e) => (self.next, e)
+                };
+                self.next = start;
+                
+                match self.grouped_condition() {
+                    Ok(c) => Ok(c),
+                    Err(_) if self.next < compare_error.0 => {
+                        self.next = compare_error.0;
+                        Err(compare_error.1)
+                    }
+                    Err(e) => Err(e)
                 }
-                v
             }
+            _ => self.comparison()
         }
     }
-}
-
-fn condition<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    fn odd_expression<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            
-            
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("ODD"));
-            let ex = expression();
-            ret AstNode::Odd(Box::new(ex))
-        }
+    
+    fn comparison(&mut self) -> ParseResult<Cond> {
+        let lhs = self.expression()?;
+        let op = match self.peek().and_then(ex_op) {
+            Some(op) => op,
+            None => return Err(self.error("comparison operator"))
+        };
+        self.advance();
+        let rhs = self.expression()?;
+        
+        Ok(Cond::Compare {lhs, op, rhs})
     }
-
-    fn composed_expression<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            let ex1 = expression();
-            
-            let op = ex_op();
-            let ex2 = expression();
-            ret AstNode::ComposedExpression{ex1: Box::new(ex1), op: op, ex2: Box::new(ex2)}
-        }
+    
+    fn grouped_condition(&mut self) -> ParseResult<Cond> {
+        self.expect_separator("(")?;
+        let cond = self.nested(Self::condition)?;
+        self.expect_separator(")")?;
+        Ok(cond)
     }
     
-    parse!{i;
-        
-        let ret = or(odd_expression, composed_expression);
-        ret ret
+    fn statement(&mut self) -> ParseResult<Stmt> {
+        self.nested(Self::unnested_statement)
     }
-}
-
-
-
-fn statement<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    fn assignment<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            let ident = ident();
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some(":="));
-            
-            let ex = expression();
-            ret AstNode::Assignment {
-                ident: Box::new(ident),
-                expression: Box::new(ex)
+    
+    // The first token decides which statement this is. Anything that
+    // cannot start one is left for the caller as an empty statement.
+    fn unnested_statement(&mut self) -> ParseResult<Stmt> {
+        let start = self.next;
+        match self.peek().map(|t| &t.kind) {
+            Some(&TokenKind::Ident(_)) => {
+                let target = self.ident()?;
+                self.expect_separator(":=")?;
+                let value = self.expression()?;
+                Ok(Stmt::Assign {span: self.span_from(start), target, value})
+            }
+            Some(&TokenKind::Keyword("CALL")) => {
+                self.advance();
+                let name = self.ident()?;
+                Ok(Stmt::Call(self.span_from(start), name))
+            }
+            Some(&TokenKind::Separator("?")) => {
+                self.advance();
+                let name = self.ident()?;
+                Ok(Stmt::Read(self.span_from(start), name))
+            }
+            Some(&TokenKind::Separator("!")) => {
+                self.advance();
+                let (items, newline) = self.write_items()?;
+                Ok(Stmt::Write {span: self.span_from(start), items, newline})
             }
+            Some(&TokenKind::Keyword("BEGIN")) => {
+                self.advance();
+                let statements = self.statement_list();
+                Ok(Stmt::BeginEnd(self.span_from(start), statements))
+            }
+            Some(&TokenKind::Keyword("IF")) => {
+                self.advance();
+                let condition = self.condition()?;
+                self.expect_keyword("THEN")?;
+                let statement = self.statement()?;
+                Ok(Stmt::IfThen { span: self.span_from(start), condition, statement: Box::new(statement) })
+            }
+            Some(&TokenKind::Keyword("WHILE")) => {
+                self.advance();
+                let condition = self.condition()?;
+                self.expect_keyword("DO")?;
+                let statement = self.statement()?;
+                Ok(Stmt::WhileDo { span: self.span_from(start), condition, statement: Box::new(statement) })
+            }
+            _ => Ok(Stmt::Empty)
         }
     }
     
-    fn call<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
+    // The items and whether to end the line. A trailing comma suppresses
+    // the newline, e.g. `! "x = ", x,`.
+    fn write_items(&mut self) -> ParseResult<(Vec<WriteItem>, bool)> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                Some(token @ &Token { kind: TokenKind::Str(s), .. }) => {
+                    self.next += 1;
+                    items.push(WriteItem::Str(token_span(token), unescape_string(s)));
+                }
+                _ => items.push(WriteItem::Expr(self.expression()?))
+            }
             
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("CALL"));
+            if !self.is_separator(",") {
+                return Ok((items, true));
+            }
+            self.advance();
             
-            let ident = ident();
-            ret AstNode::Call(Box::new(ident))
+            let more = self.peek().is_some_and(|t| matches!(t.kind, TokenKind::Str(_)) || starts_expression(t));
+            if !more {
+                return Ok((items, false));
+            }
         }
     }
     
-    fn question_mark<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("?"));
-            let ident = ident();
-            ret AstNode::QuestionMark(Box::new(ident))
+    // Skips to the next `;`, `END`, `PROCEDURE` or `.`, where parsing can
+    // pick up again. A nested `BEGIN ... END` is skipped as a whole.
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        while let Some(t) = self.peek() {
+            match t.kind {
+                TokenKind::Keyword("BEGIN") => depth += 1,
+                TokenKind::Keyword("END") if depth > 0 => depth -= 1,
+                TokenKind::Keyword("END") | TokenKind::Separator(";") if depth == 0 => return,
+                TokenKind::Keyword("PROCEDURE") | TokenKind::Separator(".") => return,
+                _ => {}
+            }
+            self.next += 1;
         }
     }
     
-    fn exclaimation<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("!"));
-            let ex = expression();
-            ret AstNode::ExclaimationMark(Box::new(ex))
+    // A statement, or an error node in its place with the rest of it skipped.
+    fn recovering_statement(&mut self) -> Stmt {
+        match self.statement() {
+            Ok(stmt) => stmt,
+            Err(e) => {
+                self.synchronize();
+                Stmt::Error(e)
+            }
         }
     }
     
-    fn begin_end_block<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("BEGIN"));
-            
-            let statements: Vec<AstNode<'a>> = sep_by1(statement, |idx| satisfy_with(idx, token_separator_cotent, |sep| sep == Some(";")));
-            
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("END"));
+    // `statement {";" statement} END`, after the `BEGIN`. Anything that does
+    // not read as a statement becomes an error node and parsing goes on
+    // after the next `;`. Without an `END` the list stops at `PROCEDURE`,
+    // `.` or the end of input.
+    fn statement_list(&mut self) -> Vec<Stmt> {
+        let mut statements = Vec::new();
+        
+        loop {
+            let stmt = self.recovering_statement();
+            let failed = matches!(stmt, Stmt::Error(_));
+            statements.push(stmt);
             
-            ret AstNode::BeginEnd({
-                statements
-            })
+            if !failed && !self.is_separator(";") && !self.is_keyword("END") {
+                statements.push(Stmt::Error(self.error("`;` or `END`")));
+                self.synchronize();
+            }
+            if self.is_separator(";") {
+                self.advance();
+            } else {
+                if self.is_keyword("END") {
+                    self.advance();
+                }
+                return statements;
+            }
         }
     }
     
-    fn if_then<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("IF"));
-            let cod = condition();
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("THEN"));
-            let st = statement();
+    fn const_declaration(&mut self) -> ParseResult<Vec<Decl>> {
+        let mut decls = Vec::new();
+        self.expect_keyword("CONST")?;
+        loop {
+            let name = self.ident()?;
+            self.expect_separator("=")?;
+            let (value_span, value, radix) = self.number()?;
+            decls.push(Decl::Const {span: name.span.to(value_span), name, value, radix});
             
-            ret AstNode::IfThen {
-                condition: Box::new(cod),
-                statement: Box::new(st)
+            if !self.is_separator(",") {
+                break;
             }
+            self.advance();
         }
+        self.list_end()?;
+        Ok(decls)
     }
     
-    fn while_do<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
+    fn var_declaration(&mut self) -> ParseResult<Vec<Decl>> {
+        let mut decls = Vec::new();
+        self.expect_keyword("VAR")?;
+        loop {
+            decls.push(Decl::Var(self.ident()?));
             
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("WHILE"));
-            let cod = condition();
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("DO"));
-            let st = statement();
-            
-            ret AstNode::WhileDo {
-                condition: Box::new(cod),
-                statement: Box::new(st)
+            if !self.is_separator(",") {
+                break;
             }
+            self.advance();
         }
+        self.list_end()?;
+        Ok(decls)
     }
     
-    fn all_choices<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            assignment()
-            <|> call()
-            <|> question_mark()
-            <|> exclaimation()
-            <|> begin_end_block()
-            <|> if_then()
-            <|> while_do()
+    // The `;` after a list of constants or variables.
+    fn list_end(&mut self) -> ParseResult<()> {
+        if self.is_separator(";") {
+            self.advance();
+            Ok(())
+        } else {
+            Err(self.error("`,` or `;`"))
         }
     }
-        
-    parse!{i;
-        let s = option(all_choices, AstNode::Number(0));
-        ret s
+    
+    // `PROCEDURE name ;`
+    fn procedure_heading(&mut self) -> ParseResult<Ident> {
+        self.expect_keyword("PROCEDURE")?;
+        let name = self.ident()?;
+        self.expect_separator(";")?;
+        Ok(name)
     }
-}
-
-fn block<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    fn const_declaration<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, Vec<AstNode<'a>>> {
-        fn sub_const_decl<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-            parse!{i;
-                let ident = ident();
-                let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("="));
-                let num = number();
-                
-                ret AstNode::Const {
-                    ident: Box::new(ident),
-                    value: Box::new(num)
+    
+    // A bad declaration becomes an error node and is skipped up to and
+    // including its `;`.
+    fn skip_declaration(&mut self, e: SyntaxError, decls: &mut Vec<Decl>) {
+        decls.push(Decl::Error(e));
+        self.synchronize();
+        if self.is_separator(";") {
+            self.advance();
+        }
+    }
+    
+    fn block(&mut self) -> Block {
+        let start = self.next;
+        let mut decls = Vec::new();
+        
+        if self.is_keyword("CONST") {
+            match self.const_declaration() {
+                Ok(consts) => decls.extend(consts),
+                Err(e) => self.skip_declaration(e, &mut decls)
+            }
+        }
+        if self.is_keyword("VAR") {
+            match self.var_declaration() {
+                Ok(vars) => decls.extend(vars),
+                Err(e) => self.skip_declaration(e, &mut decls)
+            }
+        }
+        while self.is_keyword("PROCEDURE") {
+            // The body is read even after a bad heading, so that parsing
+            // stays in step; its errors are kept and the procedure itself is
+            // dropped.
+            let start = self.next;
+            let name = match self.procedure_heading() {
+                Ok(name) => Some(name),
+                Err(e) => {
+                    self.skip_declaration(e, &mut decls);
+                    None
                 }
+            };
+            // Too deeply nested, the rest of the input cannot be read.
+            if self.depth >= MAX_NESTING {
+                decls.push(Decl::Error(self.nesting_error()));
+                self.next = self.tokens.len();
+                break;
+            }
+            self.depth += 1;
+            let body = self.block();
+            self.depth -= 1;
+            match name {
+                Some(name) => decls.push(Decl::Procedure {span: self.span_from(start), name, block: Box::new(body)}),
+                None => decls.extend(syntax_errors_in(&body).into_iter().map(Decl::Error))
+            }
+            
+            // A missing `;` is taken as read when what follows could start
+            // the next procedure or the statement.
+            if self.is_separator(";") {
+                self.advance();
+            } else if self.is_keyword("PROCEDURE") || self.peek().is_some_and(starts_statement) {
+                decls.push(Decl::Error(self.error("`;`")));
+            } else {
+                let e = self.error("`;`");
+                self.skip_declaration(e, &mut decls);
             }
         }
+        let statement = self.recovering_statement();
         
-        parse!{i;
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("CONST"));
-            
-            let subs: Vec<AstNode<'a>> = sep_by1(sub_const_decl, |idx| satisfy_with(idx, token_separator_cotent, |sep| sep == Some(",")));
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some(";"));
-            
-            ret subs
+        let span = if self.next > start { Some(self.span_from(start)) } else { None };
+        Block { span, decls, statement }
+    }
+}
+
+// Collects the error nodes of a tree.
+#[derive(Default)]
+struct ErrorCollector {
+    errors: Vec<SyntaxError>,
+}
+
+impl<'ast> Visitor<'ast> for ErrorCollector {
+    fn visit_decl(&mut self, decl: &'ast Decl) {
+        match *decl {
+            Decl::Error(ref e) => self.errors.push(e.clone()),
+            _ => walk_decl(self, decl)
         }
     }
     
-    fn var_declaration<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, Vec<AstNode<'a>>> {
-        
-        parse!{i;
-            
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("VAR"));
-            let subs: Vec<AstNode<'a>> = sep_by1(ident, |idx| satisfy_with(idx, token_separator_cotent, |sep| sep == Some(",")));
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some(";"));
-            
-            ret subs
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        match *stmt {
+            Stmt::Error(ref e) => self.errors.push(e.clone()),
+            _ => walk_stmt(self, stmt)
         }
     }
+}
+
+// An unfinished `BEGIN` nested in another reports the same token for both,
+// so only the first error at each token is kept.
+fn dedup(errors: &mut Vec<SyntaxError>) {
+    errors.dedup_by(|later, earlier| later.found == earlier.found);
+}
+
+fn syntax_errors_in(block: &Block) -> Vec<SyntaxError> {
+    let mut collector = ErrorCollector::default();
+    collector.visit_block(block);
+    dedup(&mut collector.errors);
+    collector.errors
+}
+
+/// The error nodes of `program`, in source order.
+pub fn syntax_errors(program: &Program) -> Vec<SyntaxError> {
+    syntax_errors_in(&program.block)
+}
+
+/// Every syntax error in a source, in order, and what could be made of it.
+/// The parts of `partial` that could not be read are `Error` nodes; a
+/// missing `.` and anything after it have no node.
+#[derive(Debug)]
+pub struct ParseFailure {
+    pub errors: Vec<SyntaxError>,
+    pub partial: Box<Program>,
+}
+
+/// Parses a whole token stream, recovering from errors to report them all.
+pub fn parse_program(tokens: &[Token]) -> Result<Program, ParseFailure> {
+    let mut parser = Parser::new(tokens);
+    let block = parser.block();
+    let mut errors = syntax_errors_in(&block);
     
-    fn procedure<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-        parse!{i;
-            let _ = satisfy_with(token_keyword_cotent, |sep| sep == Some("PROCEDURE"));
-            let ident = ident();
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some(";"));
-            let block = block();
-            let _ = satisfy_with(token_separator_cotent, |sep| sep == Some(";"));
-            
-            ret AstNode::Procedure {
-                ident: Box::new(ident),
-                block: Box::new(block)
-            }
+    if !parser.is_separator(".") {
+        errors.push(parser.error("`.`"));
+    } else {
+        parser.advance();
+        if parser.peek().is_some() {
+            errors.push(parser.error("end of input"));
         }
     }
+    dedup(&mut errors);
     
-    parse!{i;
-        
-        let c = option(const_declaration, Vec::new());
-        let v = option(var_declaration, Vec::new());
-        let p: Vec<AstNode<'a>> = many(procedure);
-        let s = statement();
-        
-        ret AstNode::Block {
-            const_decl: c,
-            var_decl: v,
-            procedures: p,
-            statement: Box::new(s)
+    if errors.is_empty() {
+        Ok(Program { block })
+    } else {
+        Err(ParseFailure { errors, partial: Box::new(Program { block }) })
+    }
+}
+
+#[test]
+fn test_condition_precedence() {
+    let tokens = r_lexer("NOT x = 1 OR y = 2 AND ODD z").unwrap();
+    let ast = Parser::new(&tokens).condition().unwrap();
+
+    match ast {
+        Cond::Logic {lhs, op: LogicOp::Or, rhs} => {
+            assert!(matches!(*lhs, Cond::Not(..)));
+            assert!(matches!(*rhs, Cond::Logic {op: LogicOp::And, ..}));
         }
+        _ => panic!("unexpected condition: {:?}", ast)
     }
 }
 
-pub fn program<'a>(i: Input<'a, Token>) -> SimpleResult<'a, Token<'a>, AstNode<'a>> {
-    parse!{i;
-        
-        let block = block();
-        let _ = satisfy_with(token_separator_cotent, |sep| sep == Some("."));
-        ret block
+#[test]
+fn test_grouped_condition() {
+    let tokens = r_lexer("(x + 1 > 2 OR y < 0) AND (x) # y").unwrap();
+    let ast = Parser::new(&tokens).condition().unwrap();
+
+    match ast {
+        Cond::Logic {lhs, op: LogicOp::And, rhs} => {
+            assert!(matches!(*lhs, Cond::Logic {op: LogicOp::Or, ..}));
+            assert!(matches!(*rhs, Cond::Compare {op: ExOp::NumberSign, ..}));
+        }
+        _ => panic!("unexpected condition: {:?}", ast)
+    }
+}
+
+#[test]
+fn test_write_list() {
+    let tokens = r_lexer(r#"! "x = ", x + 1, "!","#).unwrap();
+    let ast = Parser::new(&tokens).statement().unwrap();
+
+    match ast {
+        Stmt::Write {items, newline, ..} => {
+            assert_eq!(items.len(), 3);
+            assert!(matches!(items[0], WriteItem::Str(_, ref s) if s == "x = "));
+            assert!(matches!(items[1], WriteItem::Expr(Expr::Binary {op: BinOp::Add, ..})));
+            assert!(!newline);
+        }
+        _ => panic!("unexpected statement: {:?}", ast)
+    }
+}
+
+#[test]
+fn test_mod_and_negated_factor() {
+    let tokens = r_lexer("a * -b MOD 3 % -(c)").unwrap();
+    let ast = Parser::new(&tokens).expression().unwrap();
+
+    // ((a * -b) MOD 3) % -c
+    match ast {
+        Expr::Binary {ref lhs, op: BinOp::Mod, ref rhs, ..} => {
+            assert!(matches!(**rhs, Expr::Negate(_, ref f) if matches!(**f, Expr::Ident(_))));
+            match **lhs {
+                Expr::Binary {ref lhs, op: BinOp::Mod, pos, ..} => {
+                    assert_eq!(pos, Pos { line: 1, col: 8 });
+                    assert!(matches!(**lhs, Expr::Binary {op: BinOp::Mul, ref rhs, ..} if matches!(**rhs, Expr::Negate(..))));
+                }
+                _ => panic!("unexpected term: {:?}", lhs)
+            }
+        }
+        _ => panic!("unexpected expression: {:?}", ast)
+    }
+}
+
+#[test]
+fn test_block_and_empty_statements() {
+    let tokens = r_lexer("CONST n = 0x10; VAR x, y; PROCEDURE p; ; BEGIN ; x := n END.").unwrap();
+    let ast = parse_program(&tokens).unwrap();
+
+    let decls = &ast.block.decls;
+    assert!(matches!(decls[..], [Decl::Const {value: 16, radix: Radix::Hex, ..}, Decl::Var(_), Decl::Var(_), Decl::Procedure {..}]));
+    assert!(matches!(decls[3], Decl::Procedure {ref block, ..} if block.statement == Stmt::Empty));
+    assert!(matches!(decls[2], Decl::Var(ref y) if &*y.name == "y" && y.span.start == Pos { line: 1, col: 24 }));
+    match ast.block.statement {
+        Stmt::BeginEnd(_, ref statements) => {
+            assert_eq!(statements[0], Stmt::Empty);
+            assert!(matches!(statements[1], Stmt::Assign {..}));
+        }
+        ref s => panic!("unexpected statement: {:?}", s)
+    }
+}
+
+#[test]
+fn test_error_recovery() {
+    let tokens = r_lexer("VAR x, ; PROCEDURE p; x := 1 + ; BEGIN x := 2 3; IF x > THEN ; CALL p END. x").unwrap();
+    let failure = parse_program(&tokens).unwrap_err();
+
+    let messages: Vec<String> = failure.errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(messages, [
+        "1:8: expected identifier, found `;`",
+        "1:32: expected expression, found `;`",
+        "1:47: expected `;` or `END`, found `3`",
+        "1:57: expected expression, found `THEN`",
+        "1:76: expected end of input, found `x`",
+    ]);
+
+    // What could be read is kept, with error nodes where it could not.
+    let block = &failure.partial.block;
+    assert!(matches!(block.decls[..], [Decl::Error(_), Decl::Procedure {ref block, ..}] if matches!(block.statement, Stmt::Error(_))));
+    match block.statement {
+        Stmt::BeginEnd(_, ref statements) => {
+            assert!(matches!(statements[..], [Stmt::Assign {..}, Stmt::Error(_), Stmt::Error(_), Stmt::Call(..)]));
+        }
+        ref s => panic!("unexpected statement: {:?}", s)
+    }
+    assert_eq!(syntax_errors(&failure.partial), failure.errors[..4]);
+}
+
+#[test]
+fn test_missing_end() {
+    let tokens = r_lexer("BEGIN x := 1; BEGIN x := 2").unwrap();
+    let failure = parse_program(&tokens).unwrap_err();
+
+    assert_eq!(failure.errors.len(), 1);
+    assert_eq!(failure.errors[0].to_string(), "expected `;` or `END`, found end of input");
+    assert!(parse_program(&r_lexer("BEGIN x := 1 .").unwrap()).is_err());
+}
+
+#[test]
+fn test_error_spans() {
+    let tokens = r_lexer("VAR x; BEGIN x := (x + 1) THEN END.").unwrap();
+    let e = &parse_program(&tokens).unwrap_err().errors[0];
+
+    assert_eq!(e.expected, "`;` or `END`");
+    assert_eq!(e.found, Some((Span { start: Pos { line: 1, col: 27 }, end: Pos { line: 1, col: 30 } }, "THEN".to_string())));
+
+    // A parenthesised condition reports whichever reading got further.
+    let tokens = r_lexer("IF (x + 1) THEN x := 1").unwrap();
+    assert_eq!(Parser::new(&tokens).statement().unwrap_err().to_string(), "1:12: expected comparison operator, found `THEN`");
+    let tokens = r_lexer("WHILE (x > ) DO x := 1").unwrap();
+    assert_eq!(Parser::new(&tokens).statement().unwrap_err().to_string(), "1:12: expected expression, found `)`");
+}
+
+#[test]
+fn test_nesting_limit() {
+    use format::{format_program, FormatOptions};
+    use interpreter::{Interpreter, MemoryIo};
+
+    let nest = |open: &str, inner: &str, close: &str, n: usize| format!("{}{}{}", open.repeat(n), inner, close.repeat(n));
+    let programs = |n| vec![
+        format!("VAR x; x := {}.", nest("(", "1", ")", n)),
+        format!("VAR x; x := {}.", nest("- ", "1", "", n)),
+        format!("VAR x; IF {} THEN x := 1.", nest("NOT ", "x = 1", "", n)),
+        format!("VAR x; IF {} THEN x := 1.", nest("(", "x = 0", ")", n)),
+        format!("VAR x; x := {}.", nest("", "1", " + 1", n)),
+        format!("VAR x; x := {}.", nest("", "x", " * 2 - 1", n / 2)),
+        format!("VAR x; IF {} THEN x := 1.", nest("", "x = 0", " AND x = 0", n)),
+        format!("VAR x; IF {} THEN x := 1.", nest("", "x = 0", " OR x = 1", n)),
+        format!("VAR x; {}.", nest("BEGIN ", "x := 1", " END", n)),
+        format!("VAR x; {}.", nest("IF x = 0 THEN ", "x := 1", "", n)),
+        format!("VAR x; {}.", nest("WHILE x = 1 DO ", "x := 1", "", n)),
+        format!("VAR x; {} x := 1.", nest("PROCEDURE p; ", "", ";", n)),
+    ];
+
+    for source in programs(90) {
+        let program = parse_program(&r_lexer(&source).unwrap()).unwrap();
+        let formatted = format_program(&program, &[], FormatOptions::default());
+        assert!(parse_program(&r_lexer(&formatted).unwrap()).is_ok(), "{}", formatted);
+        Interpreter::new(program).run_with(&mut MemoryIo::new("")).unwrap();
+    }
+    for source in programs(10_000) {
+        let failure = parse_program(&r_lexer(&source).unwrap()).unwrap_err();
+        assert!(failure.errors[0].to_string().contains("expected at most 100 levels of nesting"), "{}", failure.errors[0]);
     }
-}
\ No newline at end of file
+}
+
+// Run with `cargo test --release -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_parse_program_throughput() {
+    use std::time::Instant;
+
+    let unit = "
+PROCEDURE p;
+VAR a, b;
+BEGIN
+  a := x; b := -y * 2 + (x - 1) MOD 7;
+  WHILE a # b DO BEGIN
+    IF a < b AND NOT ODD a THEN b := b - a;
+    IF (b < a) OR a = 0 THEN a := a - b
+  END;
+  ! \"a = \", a
+END;
+";
+    let source = format!("VAR x, y;\n{}BEGIN x := 1 END.", unit.repeat(8 * 1024 * 1024 / unit.len()));
+    let tokens = r_lexer(&source).unwrap();
+
+    let start = Instant::now();
+    let program = parse_program(&tokens).unwrap();
+    let secs = start.elapsed().as_secs_f64();
+
+    let procedures = program.block.decls.len() - 2;
+    println!("parsed {} tokens ({} procedures) in {:.3}s: {:.1}M tokens/s", tokens.len(), procedures, secs, tokens.len() as f64 / secs / 1e6);
+}
@@ -0,0 +1,237 @@
+use ast::{Block, Decl, Ident, Program};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// An interned identifier.
+///
+/// Every `Symbol` comes from `Symbol::intern`, so two symbols with the same
+/// name share one allocation and compare by pointer. Symbols own their text,
+/// which lets the AST outlive the source and be sent to other threads.
+///
+/// The interner is global. A name stays in it after its last `Symbol` is
+/// dropped until the interner next grows to twice its size, when names no
+/// longer in use are removed.
+#[derive(Clone)]
+pub struct Symbol(Arc<str>);
+
+struct Interner {
+    names: HashSet<Arc<str>>,
+    // Size at which unused names are next removed.
+    prune_at: usize,
+}
+
+const MIN_PRUNE_AT: usize = 1024;
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(|| Mutex::new(Interner { names: HashSet::new(), prune_at: MIN_PRUNE_AT }))
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Symbol {
+        let mut interner = interner().lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(s) = interner.names.get(name) {
+            return Symbol(s.clone());
+        }
+        
+        // A count of one means only the interner holds the name, and only
+        // `intern` can hand it out again while the lock is held.
+        if interner.names.len() >= interner.prune_at {
+            interner.names.retain(|s| Arc::strong_count(s) > 1);
+            interner.prune_at = (interner.names.len() * 2).max(MIN_PRUNE_AT);
+        }
+        
+        let s: Arc<str> = Arc::from(name);
+        interner.names.insert(s.clone());
+        Symbol(s)
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Symbol) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0.as_ptr() as usize).hash(state)
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+    
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+// Symbols are written as plain strings and interned again when read.
+impl Serialize for Symbol {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Symbol {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Symbol, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        Ok(Symbol::intern(&name))
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// What a name was declared as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Const(i64),
+    Var,
+    Procedure,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Declaration {
+    pub name: Ident,
+    pub kind: SymbolKind,
+}
+
+/// The declarations of one block, in source order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    /// The procedure whose body this is, `None` for the main program.
+    pub procedure: Option<Ident>,
+    /// Index of the enclosing scope.
+    pub parent: Option<usize>,
+    pub declarations: Vec<Declaration>,
+}
+
+/// The declarations of every block: the main program at index 0, then each
+/// procedure followed by those nested in it. A block sees its own names and
+/// those of the blocks around it. Error nodes declare nothing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    pub scopes: Vec<Scope>,
+}
+
+impl SymbolTable {
+    pub fn new(program: &Program) -> Self {
+        let mut table = SymbolTable::default();
+        table.add_block(&program.block, None, None);
+        table
+    }
+    
+    fn add_block(&mut self, block: &Block, procedure: Option<&Ident>, parent: Option<usize>) {
+        let id = self.scopes.len();
+        let declarations = block.decls.iter().filter_map(|decl| {
+            let (name, kind) = match *decl {
+                Decl::Const {ref name, value, ..} => (name, SymbolKind::Const(value)),
+                Decl::Var(ref name) => (name, SymbolKind::Var),
+                Decl::Procedure {ref name, ..} => (name, SymbolKind::Procedure),
+                Decl::Error(_) => return None
+            };
+            Some(Declaration { name: name.clone(), kind })
+        }).collect();
+        self.scopes.push(Scope { procedure: procedure.cloned(), parent, declarations });
+        
+        for decl in &block.decls {
+            if let Decl::Procedure {ref name, ref block, ..} = *decl {
+                self.add_block(block, Some(name), Some(id));
+            }
+        }
+    }
+    
+    /// What `name` means inside scope `scope`, looking outwards.
+    pub fn lookup(&self, scope: usize, name: &Symbol) -> Option<&Declaration> {
+        let mut next = Some(scope);
+        while let Some(id) = next {
+            let scope = &self.scopes[id];
+            if let Some(decl) = scope.declarations.iter().rev().find(|d| d.name.name == *name) {
+                return Some(decl);
+            }
+            next = scope.parent;
+        }
+        None
+    }
+}
+
+#[test]
+fn test_symbol_interning() {
+    let name = String::from("count");
+    let a = Symbol::intern(&name);
+    drop(name);
+    let b = Symbol::intern("count");
+
+    assert_eq!(a, b);
+    assert!(Arc::ptr_eq(&a.0, &b.0));
+    assert_ne!(a, Symbol::intern("Count"));
+    assert_eq!(&*a, "count");
+    assert_eq!(format!("{} {:?}", a, a), "count \"count\"");
+}
+
+#[test]
+fn test_symbol_interner_pruned() {
+    let interned = |name: &str| {
+        interner().lock().unwrap_or_else(|e| e.into_inner()).names.contains(name)
+    };
+    let kept = Symbol::intern("interner test: kept");
+    drop(Symbol::intern("interner test: dropped"));
+
+    let mut i = 0;
+    while interned("interner test: dropped") {
+        drop(Symbol::intern(&format!("interner test: {}", i)));
+        i += 1;
+        assert!(i <= 2 * MIN_PRUNE_AT * 64, "the interner never pruned");
+    }
+    assert!(interned(&kept));
+}
+
+#[test]
+fn test_symbol_table() {
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    let program = parse_program(&r_lexer("
+CONST n = 3;
+VAR x;
+PROCEDURE p;
+  VAR x, y;
+  PROCEDURE q; ;
+  ;
+PROCEDURE r; ;
+x := n.").unwrap()).unwrap();
+    let table = SymbolTable::new(&program);
+
+    let names: Vec<(Option<&str>, Option<usize>, Vec<&str>)> = table.scopes.iter().map(|s| {
+        (s.procedure.as_ref().map(|p| &*p.name), s.parent, s.declarations.iter().map(|d| &*d.name.name).collect())
+    }).collect();
+    assert_eq!(names, vec![
+        (None, None, vec!["n", "x", "p", "r"]),
+        (Some("p"), Some(0), vec!["x", "y", "q"]),
+        (Some("q"), Some(1), vec![]),
+        (Some("r"), Some(0), vec![]),
+    ]);
+
+    let lookup = |scope, name| table.lookup(scope, &Symbol::intern(name)).map(|d| (d.name.span.start.line, d.kind));
+    assert_eq!(lookup(2, "x"), Some((5, SymbolKind::Var)));
+    assert_eq!(lookup(3, "x"), Some((3, SymbolKind::Var)));
+    assert_eq!(lookup(2, "n"), Some((2, SymbolKind::Const(3))));
+    assert_eq!(lookup(3, "q"), None);
+}
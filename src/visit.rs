@@ -0,0 +1,305 @@
+//! Traversal of the AST.
+//!
+//! Every `visit_*` method defaults to the matching `walk_*` function, which
+//! visits the children in source order. A pass overrides only the nodes it
+//! cares about and calls `walk_*` itself if it still wants the children.
+
+use ast::*;
+
+pub trait Visitor<'ast>: Sized {
+    fn visit_program(&mut self, program: &'ast Program) {
+        walk_program(self, program)
+    }
+    
+    fn visit_block(&mut self, block: &'ast Block) {
+        walk_block(self, block)
+    }
+    
+    fn visit_decl(&mut self, decl: &'ast Decl) {
+        walk_decl(self, decl)
+    }
+    
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        walk_stmt(self, stmt)
+    }
+    
+    fn visit_write_item(&mut self, item: &'ast WriteItem) {
+        walk_write_item(self, item)
+    }
+    
+    fn visit_cond(&mut self, cond: &'ast Cond) {
+        walk_cond(self, cond)
+    }
+    
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        walk_expr(self, expr)
+    }
+    
+    fn visit_ident(&mut self, _ident: &'ast Ident) {}
+}
+
+pub fn walk_program<'ast, V: Visitor<'ast>>(visitor: &mut V, program: &'ast Program) {
+    visitor.visit_block(&program.block);
+}
+
+pub fn walk_block<'ast, V: Visitor<'ast>>(visitor: &mut V, block: &'ast Block) {
+    for decl in &block.decls {
+        visitor.visit_decl(decl);
+    }
+    visitor.visit_stmt(&block.statement);
+}
+
+pub fn walk_decl<'ast, V: Visitor<'ast>>(visitor: &mut V, decl: &'ast Decl) {
+    match *decl {
+        Decl::Const {ref name, ..} | Decl::Var(ref name) => visitor.visit_ident(name),
+        Decl::Procedure {ref name, ref block, ..} => {
+            visitor.visit_ident(name);
+            visitor.visit_block(block);
+        }
+        Decl::Error(_) => {}
+    }
+}
+
+pub fn walk_stmt<'ast, V: Visitor<'ast>>(visitor: &mut V, stmt: &'ast Stmt) {
+    match *stmt {
+        Stmt::Assign {ref target, ref value, ..} => {
+            visitor.visit_ident(target);
+            visitor.visit_expr(value);
+        }
+        Stmt::Call(_, ref ident) | Stmt::Read(_, ref ident) => visitor.visit_ident(ident),
+        Stmt::Write {ref items, ..} => {
+            for item in items {
+                visitor.visit_write_item(item);
+            }
+        }
+        Stmt::BeginEnd(_, ref statements) => {
+            for s in statements {
+                visitor.visit_stmt(s);
+            }
+        }
+        Stmt::IfThen {ref condition, ref statement, ..} | Stmt::WhileDo {ref condition, ref statement, ..} => {
+            visitor.visit_cond(condition);
+            visitor.visit_stmt(statement);
+        }
+        Stmt::Empty | Stmt::Error(_) => {}
+    }
+}
+
+pub fn walk_write_item<'ast, V: Visitor<'ast>>(visitor: &mut V, item: &'ast WriteItem) {
+    match *item {
+        WriteItem::Str(..) => {}
+        WriteItem::Expr(ref ex) => visitor.visit_expr(ex),
+    }
+}
+
+pub fn walk_cond<'ast, V: Visitor<'ast>>(visitor: &mut V, cond: &'ast Cond) {
+    match *cond {
+        Cond::Odd(_, ref ex) => visitor.visit_expr(ex),
+        Cond::Compare {ref lhs, ref rhs, ..} => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        Cond::Not(_, ref cond) => visitor.visit_cond(cond),
+        Cond::Logic {ref lhs, ref rhs, ..} => {
+            visitor.visit_cond(lhs);
+            visitor.visit_cond(rhs);
+        }
+    }
+}
+
+pub fn walk_expr<'ast, V: Visitor<'ast>>(visitor: &mut V, expr: &'ast Expr) {
+    match *expr {
+        Expr::Number(..) => {}
+        Expr::Ident(ref ident) => visitor.visit_ident(ident),
+        Expr::Negate(_, ref ex) => visitor.visit_expr(ex),
+        Expr::Binary {ref lhs, ref rhs, ..} => {
+            visitor.visit_expr(lhs);
+            visitor.visit_expr(rhs);
+        }
+        Expr::Call {ref name, ref args, ..} => {
+            visitor.visit_ident(name);
+            for arg in args {
+                visitor.visit_expr(arg);
+            }
+        }
+    }
+}
+
+/// Like `Visitor`, but with mutable access so a pass can rewrite the tree in
+/// place.
+pub trait VisitorMut: Sized {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program)
+    }
+    
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block)
+    }
+    
+    fn visit_decl_mut(&mut self, decl: &mut Decl) {
+        walk_decl_mut(self, decl)
+    }
+    
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+    
+    fn visit_write_item_mut(&mut self, item: &mut WriteItem) {
+        walk_write_item_mut(self, item)
+    }
+    
+    fn visit_cond_mut(&mut self, cond: &mut Cond) {
+        walk_cond_mut(self, cond)
+    }
+    
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr)
+    }
+    
+    fn visit_ident_mut(&mut self, _ident: &mut Ident) {}
+}
+
+pub fn walk_program_mut<V: VisitorMut>(visitor: &mut V, program: &mut Program) {
+    visitor.visit_block_mut(&mut program.block);
+}
+
+pub fn walk_block_mut<V: VisitorMut>(visitor: &mut V, block: &mut Block) {
+    for decl in &mut block.decls {
+        visitor.visit_decl_mut(decl);
+    }
+    visitor.visit_stmt_mut(&mut block.statement);
+}
+
+pub fn walk_decl_mut<V: VisitorMut>(visitor: &mut V, decl: &mut Decl) {
+    match *decl {
+        Decl::Const {ref mut name, ..} | Decl::Var(ref mut name) => visitor.visit_ident_mut(name),
+        Decl::Procedure {ref mut name, ref mut block, ..} => {
+            visitor.visit_ident_mut(name);
+            visitor.visit_block_mut(block);
+        }
+        Decl::Error(_) => {}
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut>(visitor: &mut V, stmt: &mut Stmt) {
+    match *stmt {
+        Stmt::Assign {ref mut target, ref mut value, ..} => {
+            visitor.visit_ident_mut(target);
+            visitor.visit_expr_mut(value);
+        }
+        Stmt::Call(_, ref mut ident) | Stmt::Read(_, ref mut ident) => visitor.visit_ident_mut(ident),
+        Stmt::Write {ref mut items, ..} => {
+            for item in items {
+                visitor.visit_write_item_mut(item);
+            }
+        }
+        Stmt::BeginEnd(_, ref mut statements) => {
+            for s in statements {
+                visitor.visit_stmt_mut(s);
+            }
+        }
+        Stmt::IfThen {ref mut condition, ref mut statement, ..} | Stmt::WhileDo {ref mut condition, ref mut statement, ..} => {
+            visitor.visit_cond_mut(condition);
+            visitor.visit_stmt_mut(statement);
+        }
+        Stmt::Empty | Stmt::Error(_) => {}
+    }
+}
+
+pub fn walk_write_item_mut<V: VisitorMut>(visitor: &mut V, item: &mut WriteItem) {
+    match *item {
+        WriteItem::Str(..) => {}
+        WriteItem::Expr(ref mut ex) => visitor.visit_expr_mut(ex),
+    }
+}
+
+pub fn walk_cond_mut<V: VisitorMut>(visitor: &mut V, cond: &mut Cond) {
+    match *cond {
+        Cond::Odd(_, ref mut ex) => visitor.visit_expr_mut(ex),
+        Cond::Compare {ref mut lhs, ref mut rhs, ..} => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        Cond::Not(_, ref mut cond) => visitor.visit_cond_mut(cond),
+        Cond::Logic {ref mut lhs, ref mut rhs, ..} => {
+            visitor.visit_cond_mut(lhs);
+            visitor.visit_cond_mut(rhs);
+        }
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut>(visitor: &mut V, expr: &mut Expr) {
+    match *expr {
+        Expr::Number(..) => {}
+        Expr::Ident(ref mut ident) => visitor.visit_ident_mut(ident),
+        Expr::Negate(_, ref mut ex) => visitor.visit_expr_mut(ex),
+        Expr::Binary {ref mut lhs, ref mut rhs, ..} => {
+            visitor.visit_expr_mut(lhs);
+            visitor.visit_expr_mut(rhs);
+        }
+        Expr::Call {ref mut name, ref mut args, ..} => {
+            visitor.visit_ident_mut(name);
+            for arg in args {
+                visitor.visit_expr_mut(arg);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_visitor_walks_in_source_order() {
+    use lexer::r_lexer;
+    use parser::parse_program;
+
+    struct Names(Vec<String>);
+
+    impl<'ast> Visitor<'ast> for Names {
+        fn visit_ident(&mut self, ident: &'ast Ident) {
+            self.0.push(ident.name.to_string());
+        }
+    }
+
+    let ast = parse_program(&r_lexer("CONST k = 2; VAR x; PROCEDURE p; x := x * k; BEGIN ? x; CALL p; IF ODD x THEN ! x END.").unwrap()).unwrap();
+    let mut names = Names(Vec::new());
+    names.visit_program(&ast);
+
+    assert_eq!(names.0, ["k", "x", "p", "x", "x", "k", "x", "p", "x", "x"]);
+}
+
+#[test]
+fn test_visitor_mut_rewrites_in_place() {
+    use dump::to_sexp;
+    use lexer::r_lexer;
+    use parser::parse_program;
+    use symbol::Symbol;
+
+    // Renames `x` to `y` and folds negated literals.
+    struct Rewrite;
+
+    impl VisitorMut for Rewrite {
+        fn visit_ident_mut(&mut self, ident: &mut Ident) {
+            if &*ident.name == "x" {
+                ident.name = Symbol::intern("y");
+            }
+        }
+
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            walk_expr_mut(self, expr);
+            let folded = match *expr {
+                Expr::Negate(pos, ref ex) => match **ex {
+                    Expr::Number(span, n, radix) => Some(Expr::Number(Span { start: pos, end: span.end }, -n, radix)),
+                    _ => None
+                },
+                _ => None
+            };
+            if let Some(folded) = folded {
+                *expr = folded;
+            }
+        }
+    }
+
+    let mut ast = parse_program(&r_lexer("VAR x; x := x + -(-3).").unwrap()).unwrap();
+    Rewrite.visit_program_mut(&mut ast);
+
+    assert_eq!(to_sexp(&ast), to_sexp(&parse_program(&r_lexer("VAR y; y := y + 3.").unwrap()).unwrap()));
+}
@@ -0,0 +1,86 @@
+//! The library API as another crate embedding PL/0 sees it.
+
+extern crate pl0;
+
+use pl0::symbol::SymbolKind;
+use pl0::{compile, Diagnostic, IntWidth, LexerOptions, MemoryIo, Natives, Overflow, RunMode, RuntimeError};
+
+#[test]
+fn compile_and_run() {
+    let program = compile("
+CONST limit = 3;
+VAR n, total;
+BEGIN
+  ? n;
+  total := 0;
+  WHILE n > 0 DO BEGIN total := total + n; n := n - 1 END;
+  ! \"total = \", total
+END.").unwrap();
+
+    for (input, output) in [("3\n", "total = 6\n"), ("10\n", "total = 55\n")] {
+        let mut io = MemoryIo::new(input);
+        program.run(&mut io).unwrap();
+        assert_eq!(io.output, output);
+    }
+
+    let globals = &program.symbols().scopes[0].declarations;
+    assert_eq!(globals[0].kind, SymbolKind::Const(3));
+    assert_eq!(&*globals[2].name.name, "total");
+    assert_eq!(program.ast().block.decls.len(), 3);
+}
+
+#[test]
+fn diagnostics_and_modes() {
+    let diagnostics = compile("VAR x; BEGIN x := ; x := 1 2 END.").err().unwrap();
+    assert_eq!(diagnostics.to_string(), "\
+syntax error: 1:19: expected expression, found `;`
+syntax error: 1:28: expected `;` or `END`, found `2`
+");
+    let diagnostics = compile("VAR x; x := 99999999999.").err().unwrap();
+    assert!(matches!(diagnostics.errors[..], [Diagnostic::Lexical(_)]));
+
+    let options = LexerOptions { int_width: IntWidth::I64, ..LexerOptions::default() };
+    let mut program = pl0::compile_with("VAR x; BEGIN x := 99999999999; ! x * 100000000 END.", options).unwrap();
+    assert!(matches!(program.run(&mut MemoryIo::default()), Err(RuntimeError::Overflow(_))));
+
+    program.set_mode(RunMode { width: IntWidth::I64, overflow: Overflow::Saturating });
+    let mut io = MemoryIo::default();
+    program.run(&mut io).unwrap();
+    assert_eq!(io.output, "9223372036854775807\n");
+}
+
+#[test]
+fn natives() {
+    let mut natives = Natives::new();
+    natives.procedure("beep", |io| io.write("beep\n").map_err(|e| e.to_string()))
+        .function("max", 2, |args| Ok(args[0].max(args[1])))
+        .function("fail", 0, |_| Err("no luck".to_string()));
+
+    let program = pl0::compile_with_natives("
+VAR x;
+BEGIN CALL beep; x := max(3, 7) * 2; ! x; x := fail() END.", LexerOptions::default(), natives).unwrap();
+    let mut io = MemoryIo::default();
+    let error = program.run(&mut io).err().unwrap();
+    assert_eq!(io.output, "beep\n14\n");
+    assert_eq!(error.to_string(), "3:48: `fail` failed: no luck");
+
+    let diagnostics = pl0::compile_with_natives("VAR x; x := max(1).", LexerOptions::default(), Natives::new()).err().unwrap();
+    assert_eq!(diagnostics.to_string(), "semantic error: 1:13: `max` is not declared\n");
+
+    // `p`'s own `beep` is not visible from `q`.
+    let mut natives = Natives::new();
+    natives.procedure("beep", |io| io.write("beep\n").map_err(|e| e.to_string()));
+    let program = pl0::compile_with_natives("
+PROCEDURE q; CALL beep;
+PROCEDURE p;
+   PROCEDURE beep; ! 42;
+   CALL q;
+CALL p.", LexerOptions::default(), natives).unwrap();
+    let mut io = MemoryIo::default();
+    program.run(&mut io).unwrap();
+    assert_eq!(io.output, "beep\n");
+
+    // Compiled once, run from worker threads.
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+    assert_send_sync(&program);
+}
@@ -0,0 +1,166 @@
+//! Golden-file tests for the `pl0` binary.
+//!
+//! Every `NAME.pl0` under `tests/programs` is run and its stdout compared
+//! with `NAME.out`; `NAME.in`, if present, is fed to stdin. The programs in
+//! `tests/programs/errors` fail: they must exit with a nonzero status, their
+//! `.err` files hold the expected diagnostics on stderr, and their `.out`
+//! files, if any, whatever they print before failing. To add a case, write
+//! the program and its expected output next to each other.
+
+extern crate serde_json;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+fn programs(dir: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(dir);
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "pl0"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no programs in {}", dir.display());
+    paths
+}
+
+// Runs `pl0 ARGS...` with `stdin`.
+fn run_pl0(args: &[&Path], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pl0"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+// Runs `pl0 ARGS...`, which must succeed, and returns its stdout.
+fn pl0(args: &[&Path], stdin: &str) -> String {
+    let output = run_pl0(args, stdin);
+    assert!(output.status.success(), "pl0 {:?} exited with {}: {}",
+            args, output.status, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn input_for(program: &Path) -> String {
+    fs::read_to_string(program.with_extension("in")).unwrap_or_default()
+}
+
+fn check_outputs(dir: &str, fails: bool) {
+    let mut failures = Vec::new();
+
+    for program in programs(dir) {
+        let output = run_pl0(&[&program], &input_for(&program));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let (expected_stdout, expected_stderr) = if fails {
+            (fs::read_to_string(program.with_extension("out")).unwrap_or_default(),
+             fs::read_to_string(program.with_extension("err")).unwrap())
+        } else {
+            (fs::read_to_string(program.with_extension("out")).unwrap(), String::new())
+        };
+
+        if output.status.success() == fails {
+            failures.push(format!("{}\nexited with {}\n{}", program.display(), output.status, stderr));
+        } else if stdout != expected_stdout || stderr != expected_stderr {
+            failures.push(format!("{}\n--- expected\n{}{}--- actual\n{}{}",
+                                  program.display(), expected_stdout, expected_stderr, stdout, stderr));
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn golden_programs() {
+    check_outputs("tests/programs", false);
+}
+
+#[test]
+fn golden_errors() {
+    check_outputs("tests/programs/errors", true);
+}
+
+// `pl0 fmt` must not change what a program does, and formatting twice
+// must change nothing.
+#[test]
+fn formatted_programs_behave_the_same() {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+
+    for program in programs("tests/programs") {
+        let formatted = pl0(&[Path::new("fmt"), &program], "");
+        let copy = tmp.join(program.file_name().unwrap());
+        fs::write(&copy, &formatted).unwrap();
+
+        let input = input_for(&program);
+        assert_eq!(pl0(&[&copy], &input), pl0(&[&program], &input), "{}", program.display());
+        assert_eq!(pl0(&[Path::new("fmt"), &copy], ""), formatted, "{}", program.display());
+    }
+}
+
+// An AST read from JSON gets the literal checks the lexer would have made.
+#[test]
+fn json_literals_are_checked() {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let source = tmp.join("literals.pl0");
+    fs::write(&source, "CONST k = 7;\n! k, \" \", 0x5.\n").unwrap();
+    let ast: serde_json::Value = serde_json::from_str(&pl0(&[Path::new("parse"), &source], "")).unwrap();
+
+    let mut negative = ast.clone();
+    negative["block"]["statement"]["Write"]["items"][2]["Expr"]["Number"][1] = (-5).into();
+    let negative_path = tmp.join("negative.json");
+    fs::write(&negative_path, negative.to_string()).unwrap();
+    let mut too_large = ast;
+    too_large["block"]["decls"][0]["Const"]["value"] = 99_999_999_999i64.into();
+    let too_large_path = tmp.join("too_large.json");
+    fs::write(&too_large_path, too_large.to_string()).unwrap();
+
+    let cases: [(&[&Path], &str); 2] = [
+        (&[Path::new("fmt"), &negative_path], "invalid AST: 2:11: number literal -5 is negative\n"),
+        (&[&too_large_path], "invalid AST: 1:7: number literal 99999999999 does not fit in 32 bits\n"),
+    ];
+    for &(args, expected) in cases.iter() {
+        let output = run_pl0(args, "");
+        assert!(!output.status.success(), "pl0 {:?} succeeded", args);
+        assert!(output.stdout.is_empty());
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), expected);
+    }
+    assert_eq!(pl0(&[Path::new("--int-width=64"), &too_large_path], ""), "99999999999 5\n");
+}
+
+// `pl0 program | head` stops quietly once `head` has what it wants.
+#[test]
+fn closed_stdout_stops_quietly() {
+    let tmp = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let program = tmp.join("endless.pl0");
+    fs::write(&program, "WHILE 1 = 1 DO ! \"y\".\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pl0"))
+        .arg(&program)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut line = String::new();
+    BufReader::new(child.stdout.take().unwrap()).read_line(&mut line).unwrap();
+    assert_eq!(line, "y\n");
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "exited with {}", output.status);
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+}
+
+#[test]
+fn invalid_numbers_are_usage_errors() {
+    let program = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs/primes.pl0");
+    for arg in ["--max-steps=lots", "--max-call-depth=-1", "--timeout=1s"].iter() {
+        let output = run_pl0(&[Path::new(arg), &program], "");
+        assert!(!output.status.success(), "pl0 {} succeeded", arg);
+        assert!(output.stdout.is_empty());
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), format!("usage error: invalid number in {}\n", arg));
+    }
+}